@@ -1,22 +1,48 @@
-use crate::opts::PubSubOpt;
-use bryggio_core::pub_sub::nats_client::{NatsClient, NatsClientConfig};
+use crate::opts::{DiscoverOpt, PubSubOpt};
+use bryggio_core::pub_sub::nats_client::{decode_nats_data, NatsClient, NatsClientConfig};
 use bryggio_core::pub_sub::{PubSubError, PubSubMsg, Subject};
+use bryggio_core::sensor::ds18b20::DiscoveredDs18b20;
 use bryggio_core::supervisor::config::SupervisorConfig;
+use bryggio_core::supervisor::pub_sub::SupervisorSubMsg;
+use std::path::Path;
 
-fn get_client(opt: &PubSubOpt) -> Result<NatsClient, PubSubError> {
-    let config = SupervisorConfig::try_new(&opt.config)
+fn get_client(config: &Path) -> Result<NatsClient, PubSubError> {
+    let config = SupervisorConfig::try_new(config)
         .map_err(|err| PubSubError::Configuration(err.to_string()))?;
     NatsClient::try_new(&NatsClientConfig::from(config.nats.server))
 }
 
 pub fn request(opt: &PubSubOpt) -> Result<(), PubSubError> {
-    let response =
-        get_client(opt)?.request(&Subject(opt.topic.clone()), &PubSubMsg(opt.msg.clone()))?;
+    let response = get_client(&opt.config)?
+        .request(&Subject(opt.topic.clone()), &PubSubMsg(opt.msg.clone()))?;
     println!("Response: {}", response);
     Ok(())
 }
 
 pub fn publish_command(opt: &PubSubOpt) -> Result<(), PubSubError> {
     println!("pub");
-    get_client(opt)?.publish(&Subject(opt.topic.clone()), &PubSubMsg(opt.msg.clone()))
+    get_client(&opt.config)?.publish(&Subject(opt.topic.clone()), &PubSubMsg(opt.msg.clone()))
+}
+
+pub fn discover_sensors(opt: &DiscoverOpt) -> Result<(), PubSubError> {
+    let cmd = SupervisorSubMsg::DiscoverSensors;
+    let response = get_client(&opt.config)?.request(&cmd.subject(), &cmd.into())?;
+    let sensors: Vec<DiscoveredDs18b20> = match decode_nats_data(&response.data) {
+        Ok(sensors) => sensors,
+        // The supervisor replies with an error message.
+        Err(_) => {
+            println!("{}", String::from_utf8_lossy(&response.data));
+            return Ok(());
+        }
+    };
+    if sensors.is_empty() {
+        println!("No DS18B20 sensors found");
+    }
+    for sensor in sensors {
+        match sensor.reading {
+            Ok(temp) => println!("{}\t{:.3}", sensor.address, temp),
+            Err(err) => println!("{}\t{}", sensor.address, err),
+        }
+    }
+    Ok(())
 }
@@ -10,6 +10,7 @@ fn run_subcommand(opt: Opt) -> Result<(), CliError> {
     match opt {
         Opt::Publish(opts) => brewery::publish_command(&opts).map_err(CliError::from),
         Opt::Request(opts) => brewery::request(&opts).map_err(CliError::from),
+        Opt::DiscoverSensors(opts) => brewery::discover_sensors(&opts).map_err(CliError::from),
         Opt::Install(target) => match target {
             InstallTarget::Supervisor(opt) => {
                 install::supervisor::install_supervisor(&opt).map_err(CliError::from)
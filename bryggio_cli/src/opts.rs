@@ -11,6 +11,9 @@ pub enum Opt {
     ///Request a reply on a subject.
     #[structopt(name = "request")]
     Request(PubSubOpt),
+    ///List the DS18B20 sensors on the 1-wire bus of the supervisor.
+    #[structopt(name = "discover-sensors")]
+    DiscoverSensors(DiscoverOpt),
     ///Install bryggio software.
     #[structopt(name = "install")]
     Install(InstallTarget),
@@ -24,6 +27,7 @@ impl Opt {
         match self {
             Self::Publish(opt) => opt.common.verbose,
             Self::Request(opt) => opt.common.verbose,
+            Self::DiscoverSensors(opt) => opt.common.verbose,
             Self::Install(target) => target.verbose(),
             Self::RbPiSetup(opt) => opt.common.verbose,
         }
@@ -42,6 +46,14 @@ pub struct PubSubOpt {
     pub(crate) common: Common,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct DiscoverOpt {
+    #[structopt(long)]
+    pub config: PathBuf,
+    #[structopt(flatten)]
+    pub(crate) common: Common,
+}
+
 #[derive(Debug, StructOpt)]
 pub enum InstallTarget {
     /// Install `bryggio-supervisor`
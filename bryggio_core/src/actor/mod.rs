@@ -53,7 +53,7 @@ pub enum ActorType {
     },
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ActorConfig {
     pub id: ClientId,
     #[serde(rename = "type")]
@@ -2,9 +2,11 @@ use super::ActorSignal;
 use crate::actor::ActorError;
 use crate::logger::{error, info};
 use crate::pub_sub::{
-    nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsClientConfig,
-    ClientId, PubSubClient, PubSubError, PubSubMsg, Subject,
+    heartbeat::Heartbeat, nats_client::decode_nats_data, nats_client::NatsClient,
+    nats_client::NatsClientConfig, ClientId, ClientState, PubSubClient, PubSubError, PubSubMsg,
+    Subject,
 };
+use crate::supervisor::pub_sub::SupervisorPubMsg;
 use crate::time::{TimeStamp, LOOP_PAUSE_TIME};
 use crate::{actor::Actor, pub_sub::MessageParseError};
 use nats::{Message, Subscription};
@@ -24,10 +26,24 @@ pub fn actor_turn_off_subject(id: &ClientId) -> Subject {
     Subject(format!("actor.{}.turn_off", id))
 }
 
+/// Reply to a request on [`actor_turn_off_subject`] once the output is off.
+pub const TURN_OFF_REPLY: &str = "Actor output set to zero";
+
+/// Subject for turning off an actor and ignoring new signals, the message data is the reason.
+pub fn actor_latch_subject(id: &ClientId) -> Subject {
+    Subject(format!("actor.{}.latch", id))
+}
+
+pub fn actor_reset_latch_subject(id: &ClientId) -> Subject {
+    Subject(format!("actor.{}.reset_latch", id))
+}
+
 pub struct ActorClient {
     id: ClientId,
     actor: Box<dyn Actor>,
     client: NatsClient,
+    /// Reason the actor was latched off, signals are rejected while set.
+    latched: Option<String>,
 }
 
 impl PubSubClient for ActorClient {
@@ -39,15 +55,57 @@ impl PubSubClient for ActorClient {
         );
         let sub_set_signal = self.subscribe(&actor_set_signal_subject(&self.id))?;
         let sub_turn_off = self.subscribe(&actor_turn_off_subject(&self.id))?;
+        let sub_latch = self.subscribe(&actor_latch_subject(&self.id))?;
+        let sub_reset_latch = self.subscribe(&actor_reset_latch_subject(&self.id))?;
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: self.id.clone(),
+            }
+            .subject(),
+        )?;
+        let mut heartbeat = Heartbeat::new("actor", &self.id);
         loop {
-            if let Some(contr_message) = sub_set_signal.try_next() {
-                if let Err(err) = self.update_signal(contr_message) {
+            heartbeat.beat(&self, ClientState::Active);
+            if let Some(msg) = kill_cmd.try_next() {
+                if self.kill(msg)? == ClientState::Inactive {
+                    info(
+                        &self,
+                        format!("Actor '{}' killed", self.id),
+                        &format!("actor.{}", self.id),
+                    );
+                    return Ok(());
+                }
+            }
+
+            // Latching is checked before new signals, so that no signal slips through.
+            if let Some(msg) = sub_latch.try_iter().last() {
+                if let Err(err) = self.latch(msg) {
+                    heartbeat.set_error(&err);
                     error(&self, err.to_string(), &format!("actor.{}", self.id));
                 }
+            }
+
+            if sub_reset_latch.try_iter().last().is_some() && self.latched.take().is_some() {
+                info(
+                    &self,
+                    format!("Actor '{}' reset", self.id),
+                    &format!("actor.{}", self.id),
+                );
+            }
+
+            if let Some(contr_message) = sub_set_signal.try_next() {
+                match self.update_signal(contr_message) {
+                    Ok(()) => heartbeat.clear_error(),
+                    Err(err) => {
+                        heartbeat.set_error(&err);
+                        error(&self, err.to_string(), &format!("actor.{}", self.id));
+                    }
+                }
             };
 
             if let Some(contr_message) = sub_turn_off.try_next() {
                 if let Err(err) = self.turn_off(contr_message) {
+                    heartbeat.set_error(&err);
                     error(&self, err.to_string(), &format!("actor.{}", self.id));
                 }
             };
@@ -55,11 +113,14 @@ impl PubSubClient for ActorClient {
             if let Err(err) = self.actor.set_signal() {
                 match err {
                     ActorError::ChangingToAlreadyActiveState => {}
-                    _ => error(
-                        &self,
-                        format!("Failed setting signal: '{}'", err),
-                        &format!("actor.{}", self.id),
-                    ),
+                    _ => {
+                        heartbeat.set_error(&err);
+                        error(
+                            &self,
+                            format!("Failed setting signal: '{}'", err),
+                            &format!("actor.{}", self.id),
+                        )
+                    }
                 }
             };
             sleep(LOOP_PAUSE_TIME);
@@ -78,12 +139,19 @@ impl PubSubClient for ActorClient {
 impl ActorClient {
     pub fn new(id: ClientId, actor: Box<dyn Actor>, config: &NatsClientConfig) -> Self {
         let client = NatsClient::try_new(config).unwrap();
-        ActorClient { id, actor, client }
+        ActorClient {
+            id,
+            actor,
+            client,
+            latched: None,
+        }
     }
 
     fn update_signal(&mut self, contr_message: Message) -> Result<(), PubSubError> {
         match ActorSubMsg::try_from(contr_message.clone()) {
             Ok(msg) => match msg {
+                // Controllers keep sending signals while latched, the latch itself is logged once.
+                ActorSubMsg::SetSignal(_) if self.latched.is_some() => Ok(()),
                 ActorSubMsg::SetSignal(new_signal) => {
                     let sign_res = self.actor.update_signal(&new_signal.signal);
                     match sign_res {
@@ -106,6 +174,68 @@ impl ActorClient {
         }
     }
 
+    /// Turn off the actor before letting the client exit.
+    ///
+    /// Replies with a serialised `Result<(), String>`.
+    /// If the actor cannot be turned off, the client keeps running so that it is not left
+    /// unsupervised in an unknown state.
+    fn kill(&mut self, kill_msg: Message) -> Result<ClientState, PubSubError> {
+        let report = match self.actor.turn_off() {
+            Ok(()) | Err(ActorError::ChangingToAlreadyActiveState) => Ok(()),
+            Err(err) => Err(format!("Failed turning off actor '{}': {}", self.id, err)),
+        };
+        kill_msg
+            .respond(serde_json::to_string(&report).expect("Pub sub serialization error"))
+            .map_err(|err| PubSubError::Reply {
+                task: "kill actor",
+                msg: kill_msg.clone(),
+                source: err,
+            })?;
+        match report {
+            Ok(()) => {
+                let shut_off_signal =
+                    SignalMsg::new(self.id.clone(), ActorSignal::new(self.id.clone(), 0.0));
+                self.publish(
+                    &actor_current_signal_subject(&self.id),
+                    &ActorPubMsg::CurrentSignal(shut_off_signal).into(),
+                )?;
+                Ok(ClientState::Inactive)
+            }
+            Err(err) => {
+                error(self, err, &format!("actor.{}", self.id));
+                Ok(ClientState::Active)
+            }
+        }
+    }
+
+    /// Turn off the actor and reject new signals until the latch is reset.
+    ///
+    /// Repeated latches only turn the actor off again, they are not logged.
+    fn latch(&mut self, msg: Message) -> Result<(), PubSubError> {
+        let reason = match ActorSubMsg::try_from(msg)? {
+            ActorSubMsg::Latch(reason) => reason,
+            _ => String::from("unknown"),
+        };
+        match self.actor.turn_off() {
+            Ok(()) | Err(ActorError::ChangingToAlreadyActiveState) => {}
+            Err(err) => return Err(err.into()),
+        }
+        if self.latched.replace(reason.clone()).is_none() {
+            error(
+                self,
+                format!("Actor '{}' latched off: {}", self.id, reason),
+                &format!("actor.{}", self.id),
+            );
+            let shut_off_signal =
+                SignalMsg::new(self.id.clone(), ActorSignal::new(self.id.clone(), 0.0));
+            self.publish(
+                &actor_current_signal_subject(&self.id),
+                &ActorPubMsg::CurrentSignal(shut_off_signal).into(),
+            )?;
+        }
+        Ok(())
+    }
+
     fn turn_off(&mut self, contr_message: Message) -> Result<(), PubSubError> {
         // println!(
         //     "actor pub sub: {:?}",
@@ -114,7 +244,7 @@ impl ActorClient {
         match self.actor.turn_off() {
             Ok(()) => {
                 contr_message
-                    .respond(String::from("Actor output set to zero"))
+                    .respond(String::from(TURN_OFF_REPLY))
                     .map_err(|err| PubSubError::Reply {
                         task: "turn off actor",
                         msg: contr_message.clone(),
@@ -161,6 +291,10 @@ pub enum ActorSubMsg {
     SetSignal(SignalMsg),
     #[serde(rename = "turn_off")]
     TurnOff,
+    #[serde(rename = "latch")]
+    Latch(String),
+    #[serde(rename = "reset_latch")]
+    ResetLatch,
     // #[serde(rename = "stop")]
     // Stop,
 }
@@ -175,6 +309,8 @@ impl TryFrom<Message> for ActorSubMsg {
         match sub_subject {
             "set_signal" => decode_nats_data(&msg.data),
             "turn_off" => Ok(Self::TurnOff),
+            "latch" => Ok(Self::Latch(decode_nats_data(&msg.data)?)),
+            "reset_latch" => Ok(Self::ResetLatch),
             _ => Err(MessageParseError::InvalidSubject(Subject(msg.subject))),
         }
     }
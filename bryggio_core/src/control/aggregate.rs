@@ -0,0 +1,264 @@
+//! Aggregation of several sensors into the measurement of a controller
+//!
+//! The main sensor of the controller is always the first of the aggregated sensors,
+//! followed by `sensor_ids` of the [`Aggregation`].
+//! Sensors reporting an error, or without a measurement for `max_staleness_ms`,
+//! are left out, so that one failed probe does not stop the controller.
+//! The aggregate is only missing when none of the sensors has a valid measurement.
+//!
+//! The controller is stepped once per aggregation period, when the main sensor reports or, if it
+//! has stopped reporting, when another sensor reports for the second time since the last step.
+//! An aggregate of several sensors is timestamped with the time it was received, see
+//! [`crate::control::Control::calculate_signal`].
+use crate::control::ControllerError;
+use crate::pub_sub::ClientId;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Aggregation {
+    /// Sensors aggregated with the main sensor of the controller.
+    pub sensor_ids: Vec<ClientId>,
+    pub policy: AggregationPolicy,
+    #[serde(default)]
+    pub max_staleness_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AggregationPolicy {
+    #[serde(rename = "mean")]
+    Mean,
+    #[serde(rename = "median")]
+    Median,
+    #[serde(rename = "min")]
+    Min,
+    #[serde(rename = "max")]
+    Max,
+    /// Weighted mean, with one weight per sensor, main sensor first.
+    #[serde(rename = "weighted")]
+    Weighted { weights: Vec<f32> },
+    /// The first sensor in order with a valid measurement, i.e. the main sensor if it works.
+    #[serde(rename = "fallback")]
+    Fallback,
+}
+
+impl Aggregation {
+    pub fn validate(&self, main_sensor_id: &ClientId) -> Result<(), ControllerError> {
+        if self.sensor_ids.contains(main_sensor_id) {
+            return Err(ControllerError::ParamError(format!(
+                "The main sensor '{}' is always aggregated, remove it from sensor_ids",
+                main_sensor_id
+            )));
+        }
+        if let AggregationPolicy::Weighted { weights } = &self.policy {
+            if weights.len() != self.sensor_ids.len() + 1 {
+                return Err(ControllerError::ParamError(format!(
+                    "Expected {} weights, one per sensor including the main sensor, got {}",
+                    self.sensor_ids.len() + 1,
+                    weights.len()
+                )));
+            }
+            if weights.iter().any(|weight| *weight < 0.0) || weights.iter().sum::<f32>() <= 0.0 {
+                return Err(ControllerError::ParamError(String::from(
+                    "Weights must be non-negative and not all zero",
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Aggregator {
+    sensor_ids: Vec<ClientId>,
+    policy: AggregationPolicy,
+    max_staleness_ms: Option<u64>,
+    /// Latest measurement from each sensor, with the time it was received.
+    latest: HashMap<ClientId, (TimeStamp, Option<f32>)>,
+    /// Sensors that have reported since the last step.
+    reported: HashSet<ClientId>,
+}
+
+impl Aggregator {
+    /// Without an aggregation, the main sensor is used as is.
+    pub fn new(main_sensor_id: &ClientId, aggregation: Option<&Aggregation>) -> Aggregator {
+        let mut sensor_ids = vec![main_sensor_id.clone()];
+        match aggregation {
+            Some(aggregation) => {
+                sensor_ids.extend(aggregation.sensor_ids.iter().cloned());
+                Aggregator {
+                    sensor_ids,
+                    policy: aggregation.policy.clone(),
+                    max_staleness_ms: aggregation.max_staleness_ms,
+                    latest: HashMap::new(),
+                    reported: HashSet::new(),
+                }
+            }
+            None => Aggregator {
+                sensor_ids,
+                policy: AggregationPolicy::Fallback,
+                max_staleness_ms: None,
+                latest: HashMap::new(),
+                reported: HashSet::new(),
+            },
+        }
+    }
+
+    pub fn contains(&self, sensor_id: &ClientId) -> bool {
+        self.sensor_ids.contains(sensor_id)
+    }
+
+    /// Whether several sensors are combined, rather than the main sensor used as is.
+    pub fn combines_sensors(&self) -> bool {
+        self.sensor_ids.len() > 1
+    }
+
+    /// `measurement` is `None` if the sensor reported an error.
+    ///
+    /// Returns whether the controller is due a step with the aggregated value.
+    pub fn update(
+        &mut self,
+        sensor_id: &ClientId,
+        measurement: Option<f32>,
+        now: TimeStamp,
+    ) -> bool {
+        if !self.contains(sensor_id) {
+            return false;
+        }
+        self.latest.insert(sensor_id.clone(), (now, measurement));
+        let due = sensor_id == &self.sensor_ids[0] || self.reported.contains(sensor_id);
+        match due {
+            true => self.reported.clear(),
+            false => {
+                self.reported.insert(sensor_id.clone());
+            }
+        }
+        due
+    }
+
+    /// Valid measurements with their weights, in sensor order.
+    fn valid(&self, now: TimeStamp) -> Vec<(f32, f32)> {
+        self.sensor_ids
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, sensor_id)| {
+                let (received, measurement) = self.latest.get(sensor_id)?;
+                let stale = self.max_staleness_ms.is_some_and(|max_staleness| {
+                    now.0.saturating_sub(received.0) > u128::from(max_staleness)
+                });
+                let weight = match &self.policy {
+                    AggregationPolicy::Weighted { weights } => weights[idx],
+                    _ => 1.0,
+                };
+                match stale {
+                    true => None,
+                    false => measurement.map(|meas| (meas, weight)),
+                }
+            })
+            .collect()
+    }
+
+    pub fn value(&self, now: TimeStamp) -> Option<f32> {
+        let valid = self.valid(now);
+        if valid.is_empty() {
+            return None;
+        }
+        let mut values: Vec<f32> = valid.iter().map(|(meas, _)| *meas).collect();
+        match &self.policy {
+            AggregationPolicy::Mean => Some(values.iter().sum::<f32>() / values.len() as f32),
+            AggregationPolicy::Median => {
+                values.sort_by(|a, b| a.total_cmp(b));
+                let mid = values.len() / 2;
+                match values.len() % 2 {
+                    0 => Some((values[mid - 1] + values[mid]) / 2.0),
+                    _ => Some(values[mid]),
+                }
+            }
+            AggregationPolicy::Min => values.into_iter().reduce(f32::min),
+            AggregationPolicy::Max => values.into_iter().reduce(f32::max),
+            AggregationPolicy::Weighted { .. } => {
+                let total_weight: f32 = valid.iter().map(|(_, weight)| weight).sum();
+                if total_weight <= 0.0 {
+                    return None;
+                }
+                let weighted: f32 = valid.iter().map(|(meas, weight)| meas * weight).sum();
+                Some(weighted / total_weight)
+            }
+            AggregationPolicy::Fallback => values.first().copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn aggregator(policy: &str) -> Aggregator {
+        let aggregation: Aggregation = serde_json::from_str(&format!(
+            r#"{{"sensor_ids": ["mash_mid", "mash_bottom"], "policy": {}, "max_staleness_ms": 5000}}"#,
+            policy
+        ))
+        .unwrap();
+        let main = ClientId::from("mash_top");
+        aggregation.validate(&main).unwrap();
+        let mut aggregator = Aggregator::new(&main, Some(&aggregation));
+        aggregator.update(&main, Some(64.0), TimeStamp(0));
+        aggregator.update(&ClientId::from("mash_mid"), Some(66.0), TimeStamp(0));
+        aggregator.update(&ClientId::from("mash_bottom"), Some(71.0), TimeStamp(0));
+        aggregator
+    }
+
+    #[test]
+    fn test_policies() {
+        let now = TimeStamp(1000);
+        assert_approx_eq!(aggregator(r#""mean""#).value(now).unwrap(), 67.0);
+        assert_approx_eq!(aggregator(r#""median""#).value(now).unwrap(), 66.0);
+        assert_approx_eq!(aggregator(r#""min""#).value(now).unwrap(), 64.0);
+        assert_approx_eq!(aggregator(r#""max""#).value(now).unwrap(), 71.0);
+        let weighted = aggregator(r#"{"weighted": {"weights": [2.0, 1.0, 1.0]}}"#);
+        assert_approx_eq!(weighted.value(now).unwrap(), 66.25);
+    }
+
+    #[test]
+    fn test_fallback() {
+        let mut aggregator = aggregator(r#""fallback""#);
+        let main = ClientId::from("mash_top");
+        assert_approx_eq!(aggregator.value(TimeStamp(1000)).unwrap(), 64.0);
+        aggregator.update(&main, None, TimeStamp(2000));
+        assert_approx_eq!(aggregator.value(TimeStamp(2000)).unwrap(), 66.0);
+        aggregator.update(&ClientId::from("mash_mid"), None, TimeStamp(3000));
+        assert_approx_eq!(aggregator.value(TimeStamp(3000)).unwrap(), 71.0);
+        // Stale sensors are left out as well.
+        assert_eq!(aggregator.value(TimeStamp(6000)), None);
+    }
+
+    #[test]
+    fn test_step_once_per_period() {
+        let mut aggregator = aggregator(r#""mean""#);
+        let (main, mid) = (ClientId::from("mash_top"), ClientId::from("mash_mid"));
+        assert!(aggregator.update(&main, Some(64.0), TimeStamp(1000)));
+        assert!(!aggregator.update(&mid, Some(66.0), TimeStamp(1000)));
+        assert!(aggregator.update(&main, Some(64.0), TimeStamp(2000)));
+        // Without the main sensor, a second report from another sensor starts a new period.
+        assert!(!aggregator.update(&mid, Some(66.0), TimeStamp(3000)));
+        assert!(aggregator.update(&mid, Some(66.0), TimeStamp(4000)));
+    }
+
+    #[test]
+    fn test_combines_sensors() {
+        assert!(aggregator(r#""mean""#).combines_sensors());
+        let single = Aggregator::new(&ClientId::from("mash_top"), None);
+        assert!(!single.combines_sensors());
+    }
+
+    #[test]
+    fn test_invalid_weights() {
+        let aggregation = Aggregation {
+            sensor_ids: vec![ClientId::from("mash_bottom")],
+            policy: AggregationPolicy::Weighted { weights: vec![1.0] },
+            max_staleness_ms: None,
+        };
+        assert!(aggregation.validate(&ClientId::from("mash_top")).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! Relay autotuning (Åström–Hägglund)
+//!
+//! The actor is switched between `output_high` and `output_low` whenever the measurement crosses
+//! the target, give or take `hysteresis`.
+//! This makes the process oscillate around the target, and the amplitude and period of the
+//! oscillation give the ultimate gain and period of the process, from which PID gains are
+//! computed with a [`TuningRule`].
+//!
+//! The first full oscillation is discarded, since it depends on where the experiment started.
+//! Once done, the output stays at `output_low` and the result is available from
+//! [`Control::autotune_result`].
+use crate::control::pid::{AntiWindup, PidConfig};
+use crate::control::{Control, ControllerError, State};
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AutotuneConfig {
+    #[serde(default = "default_output_high")]
+    pub output_high: f32,
+    #[serde(default)]
+    pub output_low: f32,
+    /// Distance from the target the measurement must pass before the relay switches,
+    /// should be a bit above the sensor noise.
+    #[serde(default = "default_hysteresis")]
+    pub hysteresis: f32,
+    /// Number of oscillations to average over.
+    #[serde(default = "default_cycles")]
+    pub cycles: usize,
+    #[serde(default)]
+    pub rule: TuningRule,
+}
+
+fn default_output_high() -> f32 {
+    1.0
+}
+
+fn default_hysteresis() -> f32 {
+    0.2
+}
+
+fn default_cycles() -> usize {
+    3
+}
+
+impl AutotuneConfig {
+    pub fn validate(&self) -> Result<(), ControllerError> {
+        if self.output_low >= self.output_high {
+            return Err(ControllerError::ParamError(format!(
+                "output_low must be less than output_high ({} !< {})",
+                self.output_low, self.output_high
+            )));
+        }
+        if self.hysteresis < 0.0 {
+            return Err(ControllerError::ParamError(format!(
+                "hysteresis must be non-negative ({} !>= 0.0)",
+                self.hysteresis
+            )));
+        }
+        if self.cycles == 0 {
+            return Err(ControllerError::ParamError(String::from(
+                "cycles must be at least 1",
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Rules for computing PID gains from the ultimate gain and period
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum TuningRule {
+    /// Fast, but with a fair amount of overshoot.
+    #[serde(rename = "ziegler_nichols")]
+    ZieglerNichols,
+    /// Less aggressive than Ziegler–Nichols, a good default for slow thermal processes.
+    #[default]
+    #[serde(rename = "tyreus_luyben")]
+    TyreusLuyben,
+    #[serde(rename = "some_overshoot")]
+    SomeOvershoot,
+    #[serde(rename = "no_overshoot")]
+    NoOvershoot,
+}
+
+impl TuningRule {
+    /// Returns `(kp, ki, kd)`, with the ultimate period in seconds.
+    pub fn gains(&self, ultimate_gain: f32, ultimate_period_s: f32) -> (f32, f32, f32) {
+        let (ku, pu) = (ultimate_gain, ultimate_period_s);
+        // Proportional gain, integral time and derivative time.
+        let (kp, ti, td) = match self {
+            TuningRule::ZieglerNichols => (0.6 * ku, pu / 2.0, pu / 8.0),
+            TuningRule::TyreusLuyben => (ku / 2.2, 2.2 * pu, pu / 6.3),
+            TuningRule::SomeOvershoot => (ku / 3.0, pu / 2.0, pu / 3.0),
+            TuningRule::NoOvershoot => (ku / 5.0, pu / 2.0, pu / 3.0),
+        };
+        (kp, kp / ti, kp * td)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AutotuneResult {
+    pub ultimate_gain: f32,
+    pub ultimate_period_s: f32,
+    pub rule: TuningRule,
+    pub pid: PidConfig,
+}
+
+pub struct Autotuner {
+    pub target: f32,
+    pub current_signal: f32,
+    state: State,
+    config: AutotuneConfig,
+    relay_on: Option<bool>,
+    /// Time of the latest switch to `output_high`, a full oscillation ends with the next one.
+    cycle_start: Option<TimeStamp>,
+    cycle_max: f32,
+    cycle_min: f32,
+    /// Amplitude and period in seconds of each full oscillation.
+    cycles: Vec<(f32, f32)>,
+    result: Option<AutotuneResult>,
+}
+
+impl Autotuner {
+    pub fn try_new(target: f32, config: AutotuneConfig) -> Result<Autotuner, ControllerError> {
+        config.validate()?;
+        Ok(Autotuner {
+            target,
+            current_signal: config.output_low,
+            state: State::Active,
+            config,
+            relay_on: None,
+            cycle_start: None,
+            cycle_max: f32::MIN,
+            cycle_min: f32::MAX,
+            cycles: Vec::new(),
+            result: None,
+        })
+    }
+
+    fn restart(&mut self) {
+        self.relay_on = None;
+        self.cycle_start = None;
+        self.cycle_max = f32::MIN;
+        self.cycle_min = f32::MAX;
+        self.cycles.clear();
+        self.result = None;
+    }
+
+    fn step(&mut self, measurement: f32, timestamp: TimeStamp) {
+        self.cycle_max = self.cycle_max.max(measurement);
+        self.cycle_min = self.cycle_min.min(measurement);
+        let relay_on = match self.relay_on {
+            None => measurement < self.target,
+            Some(true) => measurement <= self.target + self.config.hysteresis,
+            Some(false) => measurement < self.target - self.config.hysteresis,
+        };
+        if relay_on && self.relay_on == Some(false) {
+            if let Some(start) = self.cycle_start {
+                let period_s = timestamp.0.saturating_sub(start.0) as f32 / 1000.0;
+                let amplitude = (self.cycle_max - self.cycle_min) / 2.0;
+                self.cycles.push((amplitude, period_s));
+            }
+            self.cycle_start = Some(timestamp);
+            self.cycle_max = measurement;
+            self.cycle_min = measurement;
+            if self.cycles.len() > self.config.cycles {
+                self.result = self.compute_result();
+            }
+        }
+        self.relay_on = Some(relay_on);
+    }
+
+    fn compute_result(&self) -> Option<AutotuneResult> {
+        let cycles = &self.cycles[1..];
+        let count = cycles.len() as f32;
+        let amplitude = cycles.iter().map(|(amplitude, _)| amplitude).sum::<f32>() / count;
+        let period_s = cycles.iter().map(|(_, period)| period).sum::<f32>() / count;
+        // Correct for the hysteresis of the relay.
+        let hysteresis = self.config.hysteresis;
+        if amplitude <= hysteresis || period_s <= 0.0 {
+            return None;
+        }
+        let relay_amplitude = (self.config.output_high - self.config.output_low) / 2.0;
+        let ultimate_gain =
+            4.0 * relay_amplitude / (PI * (amplitude.powi(2) - hysteresis.powi(2)).sqrt());
+        let (kp, ki, kd) = self.config.rule.gains(ultimate_gain, period_s);
+        Some(AutotuneResult {
+            ultimate_gain,
+            ultimate_period_s: period_s,
+            rule: self.config.rule,
+            pid: PidConfig {
+                kp,
+                ki,
+                kd,
+                p_limit: None,
+                i_limit: None,
+                d_limit: None,
+                output_min: self.config.output_low,
+                output_max: self.config.output_high,
+                anti_windup: AntiWindup::Conditional,
+            },
+        })
+    }
+}
+
+impl Control for Autotuner {
+    fn calculate_signal(&mut self, measurement: Option<f32>, timestamp: TimeStamp) -> f32 {
+        if self.state == State::Inactive {
+            return 0.0;
+        }
+        if let (Some(measurement), None) = (measurement, &self.result) {
+            self.step(measurement, timestamp);
+        }
+        self.current_signal = match (&self.result, self.relay_on) {
+            (None, Some(true)) => self.config.output_high,
+            _ => self.config.output_low,
+        };
+        self.current_signal
+    }
+
+    fn get_state(&self) -> State {
+        self.state
+    }
+
+    /// A pause disturbs the oscillation, so an unfinished experiment restarts on resume.
+    fn set_state(&mut self, new_state: State) {
+        if self.state == State::Inactive && new_state == State::Active && self.result.is_none() {
+            self.restart();
+        }
+        self.state = new_state;
+    }
+
+    fn get_control_signal(&self) -> f32 {
+        self.current_signal
+    }
+
+    /// A new target restarts the experiment.
+    fn set_target(&mut self, new_target: f32) {
+        self.target = new_target;
+        self.restart();
+    }
+
+    fn get_target(&self) -> f32 {
+        self.target
+    }
+
+    fn validate_target(&self, new_target: f32) -> Result<f32, ControllerError> {
+        if (0.0..=100.0).contains(&new_target) {
+            Ok(new_target)
+        } else {
+            Err(ControllerError::InvalidTarget(
+                new_target,
+                String::from("You likely want a temp in [0, 100]C"),
+            ))
+        }
+    }
+
+    fn autotune_result(&self) -> Option<AutotuneResult> {
+        self.result.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use std::collections::VecDeque;
+
+    /// First order process with dead time, sampled once per second.
+    struct Kettle {
+        temp: f32,
+        dead_time: VecDeque<f32>,
+        time_s: u128,
+    }
+
+    impl Kettle {
+        fn new() -> Self {
+            Kettle {
+                temp: 20.0,
+                dead_time: VecDeque::from(vec![0.0; 30]),
+                time_s: 0,
+            }
+        }
+
+        /// Continues from where the previous run stopped.
+        fn run(&mut self, tuner: &mut Autotuner, steps: u128) {
+            let (ambient, gain, time_constant) = (20.0, 80.0, 300.0);
+            for _ in 0..steps {
+                let signal = tuner.calculate_signal(Some(self.temp), TimeStamp(self.time_s * 1000));
+                self.dead_time.push_back(signal);
+                let delayed = self.dead_time.pop_front().unwrap();
+                self.temp += (ambient + gain * delayed - self.temp) / time_constant;
+                self.time_s += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_relay_experiment() {
+        let config: AutotuneConfig = serde_json::from_str("{}").unwrap();
+        let mut tuner = Autotuner::try_new(60.0, config).unwrap();
+        let mut kettle = Kettle::new();
+        kettle.run(&mut tuner, 600);
+        assert!(tuner.autotune_result().is_none());
+        kettle.run(&mut tuner, 20_000);
+        let result = tuner.autotune_result().unwrap();
+        // The period of a relay oscillation is roughly four dead times for a slow process.
+        assert!((90.0..150.0).contains(&result.ultimate_period_s));
+        assert!(result.ultimate_gain > 0.0);
+        assert!(result.pid.kp > 0.0 && result.pid.ki > 0.0 && result.pid.kd > 0.0);
+        assert_approx_eq!(tuner.get_control_signal(), 0.0);
+    }
+
+    #[test]
+    fn test_tuning_rules() {
+        let (kp, ki, kd) = TuningRule::ZieglerNichols.gains(1.0, 100.0);
+        assert_approx_eq!(kp, 0.6);
+        assert_approx_eq!(ki, 0.012);
+        assert_approx_eq!(kd, 7.5);
+        let (kp, ki, _) = TuningRule::TyreusLuyben.gains(2.2, 100.0);
+        assert_approx_eq!(kp, 1.0);
+        assert_approx_eq!(ki, 1.0 / 220.0);
+    }
+}
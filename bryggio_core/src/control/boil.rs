@@ -0,0 +1,273 @@
+//! Boil controller
+//!
+//! The target is the local boiling point.
+//! The controller runs at `heat_up_power` until the boil is reached, then at `boil_power`.
+//! Holding the boiling point with a PID does not work, since the temperature plateaus
+//! slightly below it and the integral winds up.
+//!
+//! The boil is reached once the measurement is within `margin` of the boiling point and has
+//! risen less than `max_rate_per_min` over the last `window_s` seconds,
+//! or once the measurement reaches the boiling point.
+//! It is then kept until the controller is restarted, a new target only moves the boiling point.
+use crate::control::{Control, ControllerError, ControllerType, Handover, State};
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BoilConfig {
+    /// Output for a rolling boil, in [0, 1].
+    pub boil_power: f32,
+    #[serde(default = "default_heat_up_power")]
+    pub heat_up_power: f32,
+    #[serde(default = "default_margin")]
+    pub margin: f32,
+    /// Rise in degrees per minute below which the temperature counts as flat.
+    #[serde(default = "default_max_rate_per_min")]
+    pub max_rate_per_min: f32,
+    #[serde(default = "default_window_s")]
+    pub window_s: u64,
+}
+
+fn default_heat_up_power() -> f32 {
+    1.0
+}
+
+fn default_margin() -> f32 {
+    2.0
+}
+
+fn default_max_rate_per_min() -> f32 {
+    0.2
+}
+
+fn default_window_s() -> u64 {
+    120
+}
+
+impl BoilConfig {
+    pub fn validate(&self) -> Result<(), ControllerError> {
+        for (name, power) in [
+            ("boil_power", self.boil_power),
+            ("heat_up_power", self.heat_up_power),
+        ] {
+            if !(0.0..=1.0).contains(&power) {
+                return Err(ControllerError::ParamError(format!(
+                    "{} must be in [0, 1] ({})",
+                    name, power
+                )));
+            }
+        }
+        if self.margin < 0.0 || self.max_rate_per_min < 0.0 || self.window_s == 0 {
+            return Err(ControllerError::ParamError(String::from(
+                "margin and max_rate_per_min must be non-negative, window_s positive",
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub struct Controller {
+    pub target: f32,
+    pub current_signal: f32,
+    state: State,
+    config: BoilConfig,
+    /// Measurements within the detection window, oldest first.
+    history: VecDeque<(TimeStamp, f32)>,
+    boil_reached: Option<TimeStamp>,
+}
+
+impl Controller {
+    pub fn try_new(target: f32, config: BoilConfig) -> Result<Controller, ControllerError> {
+        config.validate()?;
+        Ok(Controller {
+            target,
+            current_signal: config.heat_up_power,
+            state: State::Active,
+            config,
+            history: VecDeque::new(),
+            boil_reached: None,
+        })
+    }
+
+    fn window_ms(&self) -> u128 {
+        u128::from(self.config.window_s) * 1000
+    }
+
+    /// Rise over the full detection window, `None` until the history covers it.
+    fn rate_per_min(&self) -> Option<f32> {
+        let (oldest, newest) = (self.history.front()?, self.history.back()?);
+        let span_ms = newest.0 .0.saturating_sub(oldest.0 .0);
+        if span_ms < self.window_ms() {
+            return None;
+        }
+        Some((newest.1 - oldest.1) / span_ms as f32 * 60_000.0)
+    }
+
+    fn detect(&mut self, measurement: f32, timestamp: TimeStamp) {
+        if self
+            .history
+            .back()
+            .is_none_or(|(prev, _)| timestamp > *prev)
+        {
+            self.history.push_back((timestamp, measurement));
+        }
+        // Keep the newest measurement that covers the window.
+        let window_ms = self.window_ms();
+        while self.history.len() > 1
+            && timestamp.0.saturating_sub(self.history[1].0 .0) >= window_ms
+        {
+            self.history.pop_front();
+        }
+        let near_boil = measurement >= self.target - self.config.margin;
+        let flat = self
+            .rate_per_min()
+            .is_some_and(|rate| rate <= self.config.max_rate_per_min);
+        if measurement >= self.target || (near_boil && flat) {
+            self.boil_reached = Some(timestamp);
+        }
+    }
+}
+
+impl Control for Controller {
+    fn calculate_signal(&mut self, measurement: Option<f32>, timestamp: TimeStamp) -> f32 {
+        if self.state == State::Inactive {
+            return 0.0;
+        }
+        if let (Some(measurement), None) = (measurement, self.boil_reached) {
+            self.detect(measurement, timestamp);
+        }
+        self.current_signal = match self.boil_reached {
+            Some(_) => self.config.boil_power,
+            None => self.config.heat_up_power,
+        };
+        self.current_signal
+    }
+
+    fn get_state(&self) -> State {
+        self.state
+    }
+
+    /// The temperature drops while paused, so the boil detection starts over on resume.
+    fn set_state(&mut self, new_state: State) {
+        if self.state == State::Inactive && new_state == State::Active {
+            self.history.clear();
+        }
+        self.state = new_state;
+    }
+
+    fn get_control_signal(&self) -> f32 {
+        self.current_signal
+    }
+
+    fn get_target(&self) -> f32 {
+        self.target
+    }
+
+    fn set_target(&mut self, new_target: f32) {
+        self.target = new_target;
+    }
+
+    fn validate_target(&self, new_target: f32) -> Result<f32, ControllerError> {
+        if (0.0..=100.0).contains(&new_target) {
+            Ok(new_target)
+        } else {
+            Err(ControllerError::InvalidTarget(
+                new_target,
+                String::from("The target is the local boiling point, at most 100C"),
+            ))
+        }
+    }
+
+    fn boil_reached(&self) -> Option<TimeStamp> {
+        self.boil_reached
+    }
+
+    /// Starts the boil detection from the handed over measurement, so that a controller taking
+    /// over at the boil goes straight to `boil_power`.
+    fn take_over(&mut self, handover: &Handover) {
+        if let Some(measurement) = handover.measurement {
+            self.calculate_signal(Some(measurement), handover.timestamp);
+        }
+    }
+
+    /// A boil already reached is kept.
+    fn set_params(&mut self, params: &ControllerType) -> Result<(), ControllerError> {
+        match params {
+            ControllerType::Boil(config) => {
+                config.validate()?;
+                self.config = config.clone();
+                Ok(())
+            }
+            _ => Err(ControllerError::ParamsMismatch(format!(
+                "expected boil parameters, got {:?}",
+                params
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn controller() -> Controller {
+        let config: BoilConfig = serde_json::from_str(r#"{"boil_power": 0.6}"#).unwrap();
+        Controller::try_new(99.0, config).unwrap()
+    }
+
+    #[test]
+    fn test_boil_detected_on_plateau() {
+        let mut controller = controller();
+        // Heating at 1 degree per minute, sampled every 10 s.
+        let mut temp = 88.0;
+        for t in 0..60 {
+            temp += 1.0 / 6.0;
+            let signal = controller.calculate_signal(Some(temp), TimeStamp(t * 10_000));
+            assert_approx_eq!(signal, 1.0);
+        }
+        assert!(controller.boil_reached().is_none());
+        // Plateau at 98.2, slightly below the boiling point,
+        // it takes a while before the rise over the window is small enough.
+        for t in 60..70 {
+            controller.calculate_signal(Some(98.2), TimeStamp(t * 10_000));
+        }
+        assert!(controller.boil_reached().is_none());
+        controller.calculate_signal(Some(98.2), TimeStamp(700_000));
+        assert_eq!(controller.boil_reached(), Some(TimeStamp(700_000)));
+        assert_approx_eq!(controller.get_control_signal(), 0.6);
+    }
+
+    #[test]
+    fn test_no_boil_on_plateau_far_below() {
+        let mut controller = controller();
+        for t in 0..100 {
+            controller.calculate_signal(Some(80.0), TimeStamp(t * 10_000));
+        }
+        assert!(controller.boil_reached().is_none());
+        controller.calculate_signal(Some(99.1), TimeStamp(1_000_000));
+        assert!(controller.boil_reached().is_some());
+    }
+
+    #[test]
+    fn test_take_over() {
+        let mut controller = controller();
+        controller.take_over(&Handover {
+            output: 0.3,
+            measurement: Some(99.2),
+            timestamp: TimeStamp(0),
+        });
+        assert_eq!(controller.boil_reached(), Some(TimeStamp(0)));
+        assert_approx_eq!(controller.get_control_signal(), 0.6);
+
+        let mut controller = self::controller();
+        controller.take_over(&Handover {
+            output: 0.3,
+            measurement: Some(80.0),
+            timestamp: TimeStamp(0),
+        });
+        assert!(controller.boil_reached().is_none());
+        assert_approx_eq!(controller.get_control_signal(), 1.0);
+    }
+}
@@ -0,0 +1,231 @@
+//! Cascade control, e.g. for HERMS and RIMS setups
+//!
+//! The outer loop compares the main sensor of the controller, e.g. in the mash, with the target
+//! and computes an offset, the inner target is the target plus this offset.
+//! The inner loop compares the inner sensor, e.g. in the HLT, with that target and drives the actor.
+//!
+//! The output range of the outer PID is the range of offsets,
+//! e.g. `[0, 20]` lets the HLT run up to 20 °C above the mash target.
+use crate::control::pid::{self, PidConfig};
+use crate::control::{Control, ControllerError, ControllerType, Handover, LoopStatus, State};
+use crate::pub_sub::ClientId;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CascadeConfig {
+    pub inner_sensor_id: ClientId,
+    pub outer: PidConfig,
+    pub inner: PidConfig,
+}
+
+pub struct Controller {
+    state: State,
+    outer_sensor_id: ClientId,
+    inner_sensor_id: ClientId,
+    outer: pid::Controller,
+    inner: pid::Controller,
+    outer_meas: Option<f32>,
+    inner_meas: Option<(TimeStamp, f32)>,
+    /// Output handed over by the replaced controller, until the inner loop is seeded with it.
+    handover_output: Option<f32>,
+}
+
+impl Controller {
+    pub fn try_new(
+        target: f32,
+        outer_sensor_id: ClientId,
+        config: CascadeConfig,
+    ) -> Result<Controller, ControllerError> {
+        let inner_target = target + config.outer.output_min;
+        Ok(Controller {
+            state: State::Active,
+            outer_sensor_id,
+            inner_sensor_id: config.inner_sensor_id,
+            outer: pid::Controller::try_new(target, config.outer)?,
+            inner: pid::Controller::try_new(inner_target, config.inner)?,
+            outer_meas: None,
+            inner_meas: None,
+            handover_output: None,
+        })
+    }
+
+    /// Recompute the inner loop after a new inner target, without advancing its integral.
+    fn refresh_inner(&mut self) {
+        if let Some((timestamp, meas)) = self.inner_meas {
+            self.inner.calculate_signal(Some(meas), timestamp);
+        }
+    }
+}
+
+impl Control for Controller {
+    /// Runs the outer loop, the measurement is from the main sensor of the controller.
+    fn calculate_signal(&mut self, measurement: Option<f32>, timestamp: TimeStamp) -> f32 {
+        if self.state == State::Inactive {
+            return 0.0;
+        }
+        if let Some(measurement) = measurement {
+            self.outer_meas = Some(measurement);
+            let offset = self.outer.calculate_signal(Some(measurement), timestamp);
+            self.inner.set_target(self.outer.get_target() + offset);
+            self.refresh_inner();
+        }
+        self.inner.get_control_signal()
+    }
+
+    fn secondary_measurement(
+        &mut self,
+        sensor_id: &ClientId,
+        measurement: Option<f32>,
+        timestamp: TimeStamp,
+    ) {
+        if sensor_id != &self.inner_sensor_id || self.state == State::Inactive {
+            return;
+        }
+        if let Some(measurement) = measurement {
+            self.inner_meas = Some((timestamp, measurement));
+            if let Some(output) = self.handover_output.take() {
+                self.inner.take_over(&Handover {
+                    output,
+                    measurement: Some(measurement),
+                    timestamp,
+                });
+            }
+        }
+        self.inner.calculate_signal(measurement, timestamp);
+    }
+
+    fn get_state(&self) -> State {
+        self.state
+    }
+
+    fn set_state(&mut self, new_state: State) {
+        self.outer.set_state(new_state);
+        self.inner.set_state(new_state);
+        self.state = new_state;
+    }
+
+    fn get_control_signal(&self) -> f32 {
+        self.inner.get_control_signal()
+    }
+
+    fn get_target(&self) -> f32 {
+        self.outer.get_target()
+    }
+
+    fn set_target(&mut self, new_target: f32) {
+        self.outer.set_target(new_target);
+    }
+
+    fn validate_target(&self, new_target: f32) -> Result<f32, ControllerError> {
+        self.outer.validate_target(new_target)
+    }
+
+    /// Both loops keep their state, the inner sensor can not change while running.
+    fn set_params(&mut self, params: &ControllerType) -> Result<(), ControllerError> {
+        match params {
+            ControllerType::Cascade(config) if config.inner_sensor_id == self.inner_sensor_id => {
+                config.outer.validate()?;
+                config.inner.validate()?;
+                self.outer.set_config(config.outer.clone())?;
+                self.inner.set_config(config.inner.clone())
+            }
+            ControllerType::Cascade(config) => Err(ControllerError::ParamsMismatch(format!(
+                "inner_sensor_id can not change from '{}' to '{}'",
+                self.inner_sensor_id, config.inner_sensor_id
+            ))),
+            _ => Err(ControllerError::ParamsMismatch(format!(
+                "expected cascade parameters, got {:?}",
+                params
+            ))),
+        }
+    }
+
+    /// The handed over measurement is from the main sensor, it sets the inner target.
+    /// The inner integral is seeded once the inner sensor reports, until then the handed over
+    /// output is kept.
+    fn take_over(&mut self, handover: &Handover) {
+        if let Some(measurement) = handover.measurement {
+            self.outer_meas = Some(measurement);
+            let offset = self
+                .outer
+                .calculate_signal(Some(measurement), handover.timestamp);
+            self.inner.set_target(self.outer.get_target() + offset);
+        }
+        self.inner.take_over(&Handover {
+            measurement: None,
+            ..*handover
+        });
+        self.handover_output = Some(handover.output);
+    }
+
+    fn loop_status(&self) -> Vec<LoopStatus> {
+        vec![
+            LoopStatus {
+                name: String::from("outer"),
+                sensor_id: self.outer_sensor_id.clone(),
+                target: self.outer.get_target(),
+                measurement: self.outer_meas,
+                output: self.outer.get_control_signal(),
+            },
+            LoopStatus {
+                name: String::from("inner"),
+                sensor_id: self.inner_sensor_id.clone(),
+                target: self.inner.get_target(),
+                measurement: self.inner_meas.map(|(_, meas)| meas),
+                output: self.inner.get_control_signal(),
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    const HERMS: &str = r#"
+        {
+          "inner_sensor_id": "hlt",
+          "outer": {"kp": 5.0, "ki": 0.0, "kd": 0.0, "output_min": 0.0, "output_max": 20.0},
+          "inner": {"kp": 0.2, "ki": 0.0, "kd": 0.0}
+        }"#;
+
+    #[test]
+    fn test_outer_loop_sets_inner_target() {
+        let config: CascadeConfig = serde_json::from_str(HERMS).unwrap();
+        let mut controller = Controller::try_new(65.0, ClientId::from("mash"), config).unwrap();
+        let hlt = ClientId::from("hlt");
+        controller.secondary_measurement(&hlt, Some(70.0), TimeStamp(0));
+        assert_approx_eq!(controller.get_control_signal(), 0.0);
+
+        // Mash 3 degrees below target, the HLT should be heated to 65 + 15 = 80.
+        let signal = controller.calculate_signal(Some(62.0), TimeStamp(1000));
+        assert_approx_eq!(controller.loop_status()[1].target, 80.0);
+        assert_approx_eq!(signal, 1.0);
+        controller.secondary_measurement(&hlt, Some(78.0), TimeStamp(2000));
+        assert_approx_eq!(controller.get_control_signal(), 0.4);
+
+        // Mash above target, the HLT is kept at the mash target.
+        controller.calculate_signal(Some(70.0), TimeStamp(3000));
+        assert_approx_eq!(controller.loop_status()[1].target, 65.0);
+        assert_approx_eq!(controller.get_control_signal(), 0.0);
+    }
+
+    #[test]
+    fn test_take_over() {
+        let config: CascadeConfig = serde_json::from_str(HERMS).unwrap();
+        let mut controller = Controller::try_new(65.0, ClientId::from("mash"), config).unwrap();
+        controller.take_over(&Handover {
+            output: 0.7,
+            measurement: Some(62.0),
+            timestamp: TimeStamp(0),
+        });
+        assert_approx_eq!(controller.loop_status()[1].target, 80.0);
+        assert_approx_eq!(controller.get_control_signal(), 0.7);
+
+        // The proportional term gives 0.4, the integral makes up the rest.
+        controller.secondary_measurement(&ClientId::from("hlt"), Some(78.0), TimeStamp(500));
+        assert_approx_eq!(controller.get_control_signal(), 0.7);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::control;
+use crate::time::TimeStamp;
 use std::f32;
 
 use super::ControllerError;
@@ -18,33 +19,39 @@ impl Controller {
         offset_on: f32,
         offset_off: f32,
     ) -> Result<Controller, control::ControllerError> {
-        if offset_off >= 0.0 {
-            if offset_on > offset_off {
-                Ok(Controller {
-                    target,
-                    current_signal: 0.0,
-                    previous_measurement: None,
-                    state: control::State::Active,
-                    offset_on,
-                    offset_off,
-                })
-            } else {
-                Err(control::ControllerError::ParamError(format!(
-                    "offset_on must be greater than the offset_off ({} !> {})",
-                    offset_on, offset_off,
-                )))
-            }
-        } else {
-            Err(control::ControllerError::ParamError(format!(
-                "offset_off must be non-negative ({} !>= 0.0)",
-                offset_off
-            )))
-        }
+        validate_offsets(offset_on, offset_off)?;
+        Ok(Controller {
+            target,
+            current_signal: 0.0,
+            previous_measurement: None,
+            state: control::State::Active,
+            offset_on,
+            offset_off,
+        })
+    }
+}
+
+fn validate_offsets(offset_on: f32, offset_off: f32) -> Result<(), ControllerError> {
+    if offset_off < 0.0 {
+        return Err(ControllerError::ParamError(format!(
+            "offset_off must be non-negative ({} !>= 0.0)",
+            offset_off
+        )));
+    }
+    if offset_on <= offset_off {
+        return Err(ControllerError::ParamError(format!(
+            "offset_on must be greater than the offset_off ({} !> {})",
+            offset_on, offset_off,
+        )));
     }
+    Ok(())
 }
 
 impl control::Control for Controller {
-    fn calculate_signal(&mut self, measurement: Option<f32>) -> f32 {
+    fn calculate_signal(&mut self, measurement: Option<f32>, _timestamp: TimeStamp) -> f32 {
+        if self.state == control::State::Inactive {
+            return 0.0;
+        }
         let measurement = match measurement {
             Some(measurement) => Some(measurement),
             None => self.previous_measurement,
@@ -83,6 +90,29 @@ impl control::Control for Controller {
         self.current_signal
     }
 
+    fn set_params(&mut self, params: &control::ControllerType) -> Result<(), ControllerError> {
+        match *params {
+            control::ControllerType::Hysteresis {
+                offset_on,
+                offset_off,
+            } => {
+                validate_offsets(offset_on, offset_off)?;
+                self.offset_on = offset_on;
+                self.offset_off = offset_off;
+                Ok(())
+            }
+            _ => Err(ControllerError::ParamsMismatch(format!(
+                "expected hysteresis parameters, got {:?}",
+                params
+            ))),
+        }
+    }
+
+    /// Keeps the actor on within the hysteresis band if the previous output was mostly on.
+    fn take_over(&mut self, handover: &control::Handover) {
+        self.current_signal = if handover.output >= 0.5 { 1.0 } else { 0.0 };
+    }
+
     fn validate_target(&self, new_target: f32) -> Result<f32, ControllerError> {
         if (0.0..=100.0).contains(&new_target) {
             Ok(new_target)
@@ -129,21 +159,21 @@ mod tests {
     fn test_control_under() {
         let mut controller = Controller::try_new(0.0, 2.0, 1.0).unwrap();
         controller.set_target(100.0);
-        assert_approx_eq!(controller.calculate_signal(Some(90.0)), 1.0);
+        assert_approx_eq!(controller.calculate_signal(Some(90.0), TimeStamp(0)), 1.0);
     }
 
     #[test]
     fn test_control_ower() {
         let mut controller = Controller::try_new(0.0, 2.0, 1.0).unwrap();
         controller.set_target(100.0);
-        assert_approx_eq!(controller.calculate_signal(Some(110.0)), 0.0);
+        assert_approx_eq!(controller.calculate_signal(Some(110.0), TimeStamp(0)), 0.0);
     }
 
     #[test]
     fn test_control_ower_offset_on() {
         let mut controller = Controller::try_new(0.0, 2.0, 1.0).unwrap();
         controller.set_target(100.0);
-        assert_approx_eq!(controller.calculate_signal(Some(98.5)), 0.0);
+        assert_approx_eq!(controller.calculate_signal(Some(98.5), TimeStamp(0)), 0.0);
     }
 
     #[test]
@@ -152,12 +182,12 @@ mod tests {
         controller.set_target(100.0);
 
         // Make sure controller.current_signal is 100.0
-        assert_approx_eq!(controller.calculate_signal(Some(30.0)), 1.0);
+        assert_approx_eq!(controller.calculate_signal(Some(30.0), TimeStamp(0)), 1.0);
         // Make sure controller.current_signal remains
-        assert_approx_eq!(controller.calculate_signal(Some(98.5)), 1.0);
+        assert_approx_eq!(controller.calculate_signal(Some(98.5), TimeStamp(0)), 1.0);
         // Make sure controller.current_signal is switched to 0.0
-        assert_approx_eq!(controller.calculate_signal(Some(99.5)), 0.0);
+        assert_approx_eq!(controller.calculate_signal(Some(99.5), TimeStamp(0)), 0.0);
         // Make sure controller.current_signal remains
-        assert_approx_eq!(controller.calculate_signal(Some(98.5)), 0.0);
+        assert_approx_eq!(controller.calculate_signal(Some(98.5), TimeStamp(0)), 0.0);
     }
 }
@@ -1,4 +1,5 @@
-use super::{Control, ControllerError, State};
+use super::{Control, ControllerError, ControllerType, State};
+use crate::time::TimeStamp;
 use std::f32;
 
 pub struct ManualController {
@@ -18,7 +19,10 @@ impl ManualController {
 }
 
 impl Control for ManualController {
-    fn calculate_signal(&mut self, _measurement: Option<f32>) -> f32 {
+    fn calculate_signal(&mut self, _measurement: Option<f32>, _timestamp: TimeStamp) -> f32 {
+        if self.state == State::Inactive {
+            return 0.0;
+        }
         self.current_signal = self.target;
         self.current_signal
     }
@@ -43,6 +47,17 @@ impl Control for ManualController {
         self.target
     }
 
+    /// Nothing to change, the target is the signal.
+    fn set_params(&mut self, params: &ControllerType) -> Result<(), ControllerError> {
+        match params {
+            ControllerType::Manual => Ok(()),
+            _ => Err(ControllerError::ParamsMismatch(format!(
+                "expected manual, got {:?}",
+                params
+            ))),
+        }
+    }
+
     fn validate_target(&self, new_target: f32) -> Result<f32, ControllerError> {
         if (0.0..=1.0).contains(&new_target) {
             Ok(new_target)
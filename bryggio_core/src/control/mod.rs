@@ -1,39 +1,154 @@
 use crate::pub_sub::ClientId;
+use crate::time::TimeStamp;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::f32;
 use thiserror::Error;
 
+pub mod aggregate;
+pub mod autotune;
+pub mod boil;
+pub mod cascade;
 pub mod hysteresis;
 pub mod manual;
+pub mod output;
 pub mod pid;
 pub mod pub_sub;
+pub mod ramp;
+pub use aggregate::Aggregation;
+pub use autotune::{AutotuneConfig, AutotuneResult};
+pub use boil::BoilConfig;
+pub use cascade::CascadeConfig;
+pub use output::OutputMapping;
+pub use pid::PidConfig;
 pub use pub_sub::ControllerClient;
+pub use ramp::TargetRamp;
 
 pub trait Control: Send {
-    fn calculate_signal(&mut self, measurement: Option<f32>) -> f32;
+    /// `timestamp` is the time of the measurement, or the current time if there is none.
+    ///
+    /// A measurement from a single sensor carries the timestamp of its `SensorMsg`, so that the
+    /// time between steps is not skewed by delivery delays.
+    /// An aggregate of several sensors, which may sit on nodes with other clocks, carries the
+    /// time it was received instead.
+    fn calculate_signal(&mut self, measurement: Option<f32>, timestamp: TimeStamp) -> f32;
     fn get_state(&self) -> State;
+    /// An inactive controller outputs zero and keeps its internal state as it was,
+    /// so that it continues where it left off once active again.
     fn set_state(&mut self, new_state: State);
     fn get_control_signal(&self) -> f32;
     fn get_target(&self) -> f32;
     fn set_target(&mut self, new_target: f32);
     fn validate_target(&self, new_target: f32) -> Result<f32, ControllerError>;
+    /// Measurement from a sensor other than the main sensor of the controller,
+    /// see [`ControllerType::secondary_sensor_ids`].
+    fn secondary_measurement(
+        &mut self,
+        _sensor_id: &ClientId,
+        _measurement: Option<f32>,
+        _timestamp: TimeStamp,
+    ) {
+    }
+    /// State of each control loop, for controllers with more than one.
+    fn loop_status(&self) -> Vec<LoopStatus> {
+        Vec::new()
+    }
+    /// Result of a finished autotuning experiment, only available from the autotuner.
+    fn autotune_result(&self) -> Option<AutotuneResult> {
+        None
+    }
+    /// Time the boil was reached, only available from the boil controller.
+    fn boil_reached(&self) -> Option<TimeStamp> {
+        None
+    }
+    /// Continue from the output of the controller this one replaces, instead of from zero.
+    /// Controllers without state to adjust ignore it.
+    fn take_over(&mut self, _handover: &Handover) {}
+    /// Apply new parameters in place, keeping the internal state.
+    /// The parameters must be of the same type as the controller.
+    fn set_params(&mut self, params: &ControllerType) -> Result<(), ControllerError> {
+        Err(ControllerError::ParamsMismatch(format!(
+            "{:?} can not be changed while running",
+            params
+        )))
+    }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// State of a controller handed to the controller replacing it, see [`Control::take_over`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Handover {
+    /// Signal last sent to the actor.
+    pub output: f32,
+    /// Latest valid measurement, if any.
+    pub measurement: Option<f32>,
+    pub timestamp: TimeStamp,
+}
+
+/// State of one control loop in a controller, reported in the controller status.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoopStatus {
+    pub name: String,
+    pub sensor_id: ClientId,
+    pub target: f32,
+    pub measurement: Option<f32>,
+    pub output: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 pub enum State {
+    #[serde(rename = "inactive")]
     Inactive,
+    #[default]
+    #[serde(rename = "active")]
     Active,
 }
 
 #[non_exhaustive]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ControllerType {
     #[serde(rename = "hysteresis")]
     Hysteresis { offset_on: f32, offset_off: f32 },
     #[serde(rename = "pid")]
-    Pid { kp: f32, ki: f32, kd: f32 },
+    Pid(PidConfig),
     #[serde(rename = "manual")]
     Manual,
+    #[serde(rename = "autotune")]
+    Autotune(AutotuneConfig),
+    /// Outer loop on the main sensor, inner loop on a second sensor.
+    #[serde(rename = "cascade")]
+    Cascade(CascadeConfig),
+    /// Full power up to the boil, then a fixed power for a rolling boil.
+    #[serde(rename = "boil")]
+    Boil(BoilConfig),
+}
+
+impl ControllerType {
+    /// Whether both are the same type of controller, regardless of parameters.
+    pub fn same_type(&self, other: &ControllerType) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    /// Whether the controller can produce negative signals, as needed by a split range output.
+    ///
+    /// Hysteresis, manual and boil controllers stay in [0, 1].
+    pub fn output_can_be_negative(&self) -> bool {
+        match self {
+            ControllerType::Pid(config) => config.output_min < 0.0,
+            ControllerType::Cascade(config) => config.inner.output_min < 0.0,
+            ControllerType::Autotune(config) => config.output_low < 0.0,
+            ControllerType::Hysteresis { .. }
+            | ControllerType::Manual
+            | ControllerType::Boil(_) => false,
+        }
+    }
+
+    /// Sensors used by the controller besides its main sensor.
+    pub fn secondary_sensor_ids(&self) -> Vec<&ClientId> {
+        match self {
+            ControllerType::Cascade(config) => vec![&config.inner_sensor_id],
+            _ => Vec::new(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -43,6 +158,33 @@ pub struct ControllerConfig {
     pub(crate) sensor_id: ClientId,
     #[serde(rename = "type")]
     pub type_: ControllerType,
+    #[serde(default)]
+    pub failsafe: Option<Failsafe>,
+    /// Sensors aggregated with the main sensor into the measurement of the controller.
+    #[serde(default)]
+    pub aggregation: Option<Aggregation>,
+    /// Actors driven together with the main actor, and how the signal is split over them.
+    #[serde(default)]
+    pub output: Option<OutputMapping>,
+}
+
+/// Fallback for when the sensor of a controller stops sending valid measurements
+///
+/// Once no valid measurement has arrived for `measurement_timeout_ms`, the actor is driven to
+/// `safe_output` and the controller reports itself as degraded.
+/// Normal control resumes with the next valid measurement.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Failsafe {
+    pub measurement_timeout_ms: u64,
+    #[serde(default)]
+    pub safe_output: f32,
+}
+
+impl Failsafe {
+    /// Whether the latest valid measurement, received at `last_meas`, is too old.
+    pub fn expired(&self, last_meas: TimeStamp, now: TimeStamp) -> bool {
+        now.0.saturating_sub(last_meas.0) > u128::from(self.measurement_timeout_ms)
+    }
 }
 
 impl ControllerConfig {
@@ -52,13 +194,49 @@ impl ControllerConfig {
             actor_id: ClientId("dummy_actor".into()),
             sensor_id: ClientId("dummy_sensor".into()),
             type_: ControllerType::Manual,
+            failsafe: None,
+            aggregation: None,
+            output: None,
         }
     }
     pub fn client_ids(&self) -> impl Iterator<Item = &ClientId> {
-        std::iter::once(&self.actor_id).chain(std::iter::once(&self.sensor_id))
+        self.actor_ids().chain(self.sensor_ids())
+    }
+
+    /// The main actor followed by any actors of the output mapping.
+    pub fn actor_ids(&self) -> impl Iterator<Item = &ClientId> {
+        let mapped = self
+            .output
+            .iter()
+            .flat_map(|output| output.actor_ids.iter());
+        std::iter::once(&self.actor_id).chain(mapped)
+    }
+
+    /// The main sensor, then any aggregated sensors, then any secondary sensors.
+    pub fn sensor_ids(&self) -> impl Iterator<Item = &ClientId> {
+        let aggregated = self
+            .aggregation
+            .iter()
+            .flat_map(|aggregation| aggregation.sensor_ids.iter());
+        std::iter::once(&self.sensor_id)
+            .chain(aggregated)
+            .chain(self.type_.secondary_sensor_ids())
     }
 
     pub fn get_controller(&self, target: f32) -> Result<Box<dyn Control>, ControllerError> {
+        if let Some(aggregation) = &self.aggregation {
+            aggregation.validate(&self.sensor_id)?;
+        }
+        if let Some(sensor_id) = self.sensor_ids().duplicates().next() {
+            return Err(ControllerError::ParamError(format!(
+                "Sensor '{}' is used more than once by the controller",
+                sensor_id
+            )));
+        }
+        if let Some(output) = &self.output {
+            output.validate(&self.actor_id)?;
+            output.validate_output_range(&self.type_)?;
+        }
         match self.type_ {
             ControllerType::Hysteresis {
                 offset_on,
@@ -67,12 +245,24 @@ impl ControllerConfig {
                 let control = hysteresis::Controller::try_new(target, offset_on, offset_off)?;
                 Ok(Box::new(control))
             }
-            ControllerType::Pid { kp, ki, kd } => {
-                let control = pid::Controller::new(target, kp, ki, kd, None, None, None);
+            ControllerType::Pid(ref config) => {
+                let control = pid::Controller::try_new(target, config.clone())?;
                 Ok(Box::new(control))
             }
             ControllerType::Manual { .. } => Ok(Box::new(manual::ManualController::new(target))),
-            //_ => unimplemented!(),
+            ControllerType::Autotune(ref config) => {
+                let control = autotune::Autotuner::try_new(target, config.clone())?;
+                Ok(Box::new(control))
+            }
+            ControllerType::Cascade(ref config) => {
+                let control =
+                    cascade::Controller::try_new(target, self.sensor_id.clone(), config.clone())?;
+                Ok(Box::new(control))
+            }
+            ControllerType::Boil(ref config) => {
+                let control = boil::Controller::try_new(target, config.clone())?;
+                Ok(Box::new(control))
+            }
         }
     }
 }
@@ -85,4 +275,46 @@ pub enum ControllerError {
     InvalidTarget(f32, String),
     #[error("Unknown type: {0}")]
     Type(String),
+    #[error("Parameters do not match the controller: {0}")]
+    ParamsMismatch(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::aggregate::AggregationPolicy;
+
+    #[test]
+    fn test_failsafe() {
+        let failsafe: Failsafe =
+            serde_json::from_str(r#"{"measurement_timeout_ms": 5000}"#).unwrap();
+        assert_eq!(failsafe.safe_output, 0.0);
+        assert!(!failsafe.expired(TimeStamp(1000), TimeStamp(6000)));
+        assert!(failsafe.expired(TimeStamp(1000), TimeStamp(6001)));
+    }
+
+    #[test]
+    fn test_duplicate_sensor_ids() {
+        let mut config: ControllerConfig = serde_json::from_str(
+            r#"{
+              "controller_id": "herms",
+              "actor_id": "hlt_heater",
+              "sensor_id": "mash",
+              "type": {"cascade": {
+                "inner_sensor_id": "hlt",
+                "outer": {"kp": 5.0, "ki": 0.0, "kd": 0.0},
+                "inner": {"kp": 0.2, "ki": 0.0, "kd": 0.0}
+              }},
+              "aggregation": {"sensor_ids": ["mash_bottom"], "policy": "mean"}
+            }"#,
+        )
+        .unwrap();
+        assert!(config.get_controller(66.0).is_ok());
+        config.aggregation = Some(Aggregation {
+            sensor_ids: vec![ClientId::from("hlt")],
+            policy: AggregationPolicy::Mean,
+            max_staleness_ms: None,
+        });
+        assert!(config.get_controller(66.0).is_err());
+    }
 }
@@ -0,0 +1,175 @@
+//! Mapping of the control signal onto several actors
+//!
+//! The main actor of the controller is always the first actor of the mapping,
+//! followed by `actor_ids` of the [`OutputMapping`].
+use crate::control::{ControllerError, ControllerType};
+use crate::pub_sub::ClientId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutputMapping {
+    /// Actors driven together with the main actor of the controller.
+    pub actor_ids: Vec<ClientId>,
+    pub mode: OutputMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum OutputMode {
+    /// The signal, in [0, 1], is the fraction of the total capacity,
+    /// which is filled actor by actor, e.g. a 2 kW element before a 3.5 kW one.
+    #[serde(rename = "staged")]
+    Staged { capacities: Vec<f32> },
+    /// For exactly two actors, heating and then cooling, with a signal in [-1, 1].
+    /// Positive signals drive the heater and negative ones the cooler,
+    /// neither is driven while the signal is within `deadband` of zero.
+    #[serde(rename = "split_range")]
+    SplitRange {
+        #[serde(default)]
+        deadband: f32,
+    },
+    /// Every actor gets the signal.
+    #[serde(rename = "proportional")]
+    Proportional,
+}
+
+impl OutputMapping {
+    pub fn validate(&self, main_actor_id: &ClientId) -> Result<(), ControllerError> {
+        if self.actor_ids.contains(main_actor_id) {
+            return Err(ControllerError::ParamError(format!(
+                "The main actor '{}' is always driven, remove it from actor_ids",
+                main_actor_id
+            )));
+        }
+        let actor_count = self.actor_ids.len() + 1;
+        match &self.mode {
+            OutputMode::Staged { capacities } => {
+                if capacities.len() != actor_count {
+                    return Err(ControllerError::ParamError(format!(
+                        "Expected {} capacities, one per actor including the main actor, got {}",
+                        actor_count,
+                        capacities.len()
+                    )));
+                }
+                if capacities.iter().any(|capacity| *capacity <= 0.0) {
+                    return Err(ControllerError::ParamError(String::from(
+                        "Capacities must be positive",
+                    )));
+                }
+            }
+            OutputMode::SplitRange { deadband } => {
+                if actor_count != 2 {
+                    return Err(ControllerError::ParamError(format!(
+                        "Split range needs exactly two actors, got {}",
+                        actor_count
+                    )));
+                }
+                if !(0.0..1.0).contains(deadband) {
+                    return Err(ControllerError::ParamError(format!(
+                        "deadband must be in [0, 1) ({})",
+                        deadband
+                    )));
+                }
+            }
+            OutputMode::Proportional => {}
+        }
+        Ok(())
+    }
+
+    /// A split range output is only usable by a controller that can drive the cooler.
+    pub fn validate_output_range(&self, type_: &ControllerType) -> Result<(), ControllerError> {
+        match self.mode {
+            OutputMode::SplitRange { .. } if !type_.output_can_be_negative() => {
+                Err(ControllerError::ParamError(String::from(
+                    "Split range needs a controller with a negative output_min",
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Signal of each actor, main actor first.
+    pub fn split(&self, signal: f32) -> Vec<f32> {
+        match &self.mode {
+            OutputMode::Staged { capacities } => {
+                let total: f32 = capacities.iter().sum();
+                let mut remaining = signal.clamp(0.0, 1.0) * total;
+                capacities
+                    .iter()
+                    .map(|capacity| {
+                        let stage = remaining.clamp(0.0, *capacity);
+                        remaining -= stage;
+                        stage / capacity
+                    })
+                    .collect()
+            }
+            OutputMode::SplitRange { deadband } => {
+                let scale = |signal: f32| ((signal - deadband) / (1.0 - deadband)).clamp(0.0, 1.0);
+                vec![scale(signal), scale(-signal)]
+            }
+            OutputMode::Proportional => vec![signal; self.actor_ids.len() + 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn mapping(mode: &str) -> OutputMapping {
+        let mapping: OutputMapping =
+            serde_json::from_str(&format!(r#"{{"actor_ids": ["second"], "mode": {}}}"#, mode))
+                .unwrap();
+        mapping.validate(&ClientId::from("first")).unwrap();
+        mapping
+    }
+
+    fn assert_split(mapping: &OutputMapping, signal: f32, expected: [f32; 2]) {
+        let split = mapping.split(signal);
+        assert_approx_eq!(split[0], expected[0]);
+        assert_approx_eq!(split[1], expected[1]);
+    }
+
+    #[test]
+    fn test_staged() {
+        let mapping = mapping(r#"{"staged": {"capacities": [2.0, 3.5]}}"#);
+        assert_split(&mapping, 0.0, [0.0, 0.0]);
+        assert_split(&mapping, 0.2, [0.55, 0.0]);
+        assert_split(&mapping, 0.6, [1.0, 1.3 / 3.5]);
+        assert_split(&mapping, 1.0, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_split_range() {
+        let mapping = mapping(r#"{"split_range": {"deadband": 0.2}}"#);
+        assert_split(&mapping, 0.1, [0.0, 0.0]);
+        assert_split(&mapping, -0.2, [0.0, 0.0]);
+        assert_split(&mapping, 0.6, [0.5, 0.0]);
+        assert_split(&mapping, -1.0, [0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_invalid_mapping() {
+        let mapping = OutputMapping {
+            actor_ids: vec![ClientId::from("cooler"), ClientId::from("fan")],
+            mode: OutputMode::SplitRange { deadband: 0.0 },
+        };
+        assert!(mapping.validate(&ClientId::from("heater")).is_err());
+    }
+
+    #[test]
+    fn test_split_range_needs_negative_output() {
+        let mapping = mapping(r#"{"split_range": {"deadband": 0.0}}"#);
+        let pid: ControllerType =
+            serde_json::from_str(r#"{"pid": {"kp": 1.0, "ki": 0.0, "kd": 0.0}}"#).unwrap();
+        assert!(mapping.validate_output_range(&pid).is_err());
+        let pid: ControllerType = serde_json::from_str(
+            r#"{"pid": {"kp": 1.0, "ki": 0.0, "kd": 0.0, "output_min": -1.0}}"#,
+        )
+        .unwrap();
+        assert!(mapping.validate_output_range(&pid).is_ok());
+        assert!(mapping
+            .validate_output_range(&ControllerType::Manual)
+            .is_err());
+    }
+}
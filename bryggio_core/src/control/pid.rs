@@ -1,53 +1,186 @@
+//! PID controller
+//!
+//! The output is `p + i + d`, clamped to the configured output range, which defaults to [0, 1].
+//! Zero error with an empty integral hence gives zero output.
+//! The integral and derivative terms use the time between measurement timestamps,
+//! so an irregular sensor rate does not change the effective gains.
+//! The derivative acts on the measurement rather than the error, so that a new target does not
+//! give a spike in the output.
+//! Unless configured otherwise, the integral stops growing while the output is saturated,
+//! see [`AntiWindup::Conditional`].
+//!
+//! Gains tuned for the old output, which was scaled from [-100, 100] onto [0, 1], must be
+//! divided by 200 to give the same response, and zero error no longer gives a 50 % output.
 use crate::control;
-use pid as ext_pid;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
 use std::f32;
 
 use super::ControllerError;
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PidConfig {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Limits on the magnitude of each term, unlimited if not set.
+    #[serde(default)]
+    pub p_limit: Option<f32>,
+    #[serde(default)]
+    pub i_limit: Option<f32>,
+    #[serde(default)]
+    pub d_limit: Option<f32>,
+    #[serde(default = "default_output_min")]
+    pub output_min: f32,
+    #[serde(default = "default_output_max")]
+    pub output_max: f32,
+    #[serde(default)]
+    pub anti_windup: AntiWindup,
+}
+
+fn default_output_min() -> f32 {
+    0.0
+}
+
+fn default_output_max() -> f32 {
+    1.0
+}
+
+impl PidConfig {
+    pub fn validate(&self) -> Result<(), ControllerError> {
+        if self.output_min >= self.output_max {
+            return Err(ControllerError::ParamError(format!(
+                "output_min must be less than output_max ({} !< {})",
+                self.output_min, self.output_max
+            )));
+        }
+        let limits = [self.p_limit, self.i_limit, self.d_limit];
+        if limits.iter().flatten().any(|limit| *limit < 0.0) {
+            return Err(ControllerError::ParamError(String::from(
+                "Term limits must be non-negative",
+            )));
+        }
+        if let AntiWindup::BackCalculation { tracking_gain } = self.anti_windup {
+            if tracking_gain <= 0.0 {
+                return Err(ControllerError::ParamError(format!(
+                    "tracking_gain must be positive ({} !> 0.0)",
+                    tracking_gain
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How to keep the integral term from growing while the output is saturated
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum AntiWindup {
+    /// Only clamp the integral term to `i_limit`, which does nothing if `i_limit` is not set.
+    #[serde(rename = "clamp")]
+    Clamp,
+    /// Stop integrating while the output is saturated and the error would saturate it further.
+    #[default]
+    #[serde(rename = "conditional")]
+    Conditional,
+    /// Bleed off the integral term in proportion to how much the output is saturated.
+    #[serde(rename = "back_calculation")]
+    BackCalculation { tracking_gain: f32 },
+}
+
 pub struct Controller {
     pub target: f32,
     pub current_signal: f32,
     state: control::State,
-    pid: ext_pid::Pid<f32>,
+    config: PidConfig,
+    /// Integral term, i.e. already multiplied by `ki`.
+    integral: f32,
+    previous: Option<(TimeStamp, f32)>,
 }
 
 impl Controller {
-    pub fn new(
-        target: f32,
-        kp: f32,
-        ki: f32,
-        kd: f32,
-        p_limit: Option<f32>,
-        i_limit: Option<f32>,
-        d_limit: Option<f32>,
-    ) -> Controller {
-        let p_limit = p_limit.unwrap_or(100.0);
-        let i_limit = i_limit.unwrap_or(100.0);
-        let d_limit = d_limit.unwrap_or(100.0);
-        let output_limit = 100.0;
-        let mut pid = ext_pid::Pid::new(target, output_limit);
-        pid.p(kp, p_limit).i(ki, i_limit).d(kd, d_limit);
-        let pid = pid;
-        Controller {
-            target: 0.0,
+    pub fn try_new(target: f32, config: PidConfig) -> Result<Controller, ControllerError> {
+        config.validate()?;
+        Ok(Controller {
+            target,
             current_signal: 0.0,
             state: control::State::Active,
-            pid,
+            config,
+            integral: 0.0,
+            previous: None,
+        })
+    }
+
+    /// Replace the config, the integral term is kept so that the output does not jump.
+    pub fn set_config(&mut self, config: PidConfig) -> Result<(), ControllerError> {
+        config.validate()?;
+        self.integral = clamp_term(self.integral, config.i_limit);
+        self.config = config;
+        Ok(())
+    }
+
+    fn step(&mut self, measurement: f32, timestamp: TimeStamp) -> f32 {
+        let config = &self.config;
+        let error = self.target - measurement;
+        let p = clamp_term(config.kp * error, config.p_limit);
+        // Out of order or repeated timestamps only update the proportional term.
+        let dt = self
+            .previous
+            .map(|(prev_timestamp, prev_meas)| {
+                let dt = timestamp.0.saturating_sub(prev_timestamp.0) as f32 / 1000.0;
+                (dt, prev_meas)
+            })
+            .filter(|(dt, _)| *dt > 0.0);
+        let d = match dt {
+            Some((dt, prev_meas)) => {
+                clamp_term(-config.kd * (measurement - prev_meas) / dt, config.d_limit)
+            }
+            None => 0.0,
+        };
+
+        let unsaturated = p + self.integral + d;
+        let output = unsaturated.clamp(config.output_min, config.output_max);
+        if let Some((dt, _)) = dt {
+            let increment = config.ki * error * dt;
+            self.integral += match config.anti_windup {
+                AntiWindup::Clamp => increment,
+                AntiWindup::Conditional => {
+                    let winding_up = (unsaturated > config.output_max && increment > 0.0)
+                        || (unsaturated < config.output_min && increment < 0.0);
+                    if winding_up {
+                        0.0
+                    } else {
+                        increment
+                    }
+                }
+                AntiWindup::BackCalculation { tracking_gain } => {
+                    increment + tracking_gain * (output - unsaturated) * dt
+                }
+            };
+            self.integral = clamp_term(self.integral, config.i_limit);
+        }
+        if self.previous.is_none_or(|(prev, _)| timestamp > prev) {
+            self.previous = Some((timestamp, measurement));
         }
+        output
+    }
+}
+
+fn clamp_term(term: f32, limit: Option<f32>) -> f32 {
+    match limit {
+        Some(limit) => term.clamp(-limit, limit),
+        None => term,
     }
 }
 
 impl control::Control for Controller {
-    fn calculate_signal(&mut self, measurement: Option<f32>) -> f32 {
-        let new_signal = if let Some(measurement) = measurement {
-            let pid_output = self.pid.next_control_output(measurement).output;
-            // Map PID output \in [-output_limit, output_limit] --> [0, 1]
-            (pid_output + self.pid.output_limit) / (2.0 * self.pid.output_limit)
-        } else {
-            self.current_signal
-        };
-        self.current_signal = new_signal;
-        new_signal
+    fn calculate_signal(&mut self, measurement: Option<f32>, timestamp: TimeStamp) -> f32 {
+        if self.state == control::State::Inactive {
+            return 0.0;
+        }
+        if let Some(measurement) = measurement {
+            self.current_signal = self.step(measurement, timestamp);
+        }
+        self.current_signal
     }
 
     fn get_state(&self) -> control::State {
@@ -58,13 +191,16 @@ impl control::Control for Controller {
         self.current_signal
     }
 
+    /// The time spent inactive is neither integrated nor differentiated over.
     fn set_state(&mut self, new_state: control::State) {
+        if self.state == control::State::Inactive && new_state == control::State::Active {
+            self.previous = None;
+        }
         self.state = new_state;
     }
 
     fn set_target(&mut self, new_target: f32) {
         self.target = new_target;
-        self.pid.setpoint = new_target;
     }
 
     fn get_target(&self) -> f32 {
@@ -81,4 +217,151 @@ impl control::Control for Controller {
             ))
         }
     }
+
+    fn set_params(&mut self, params: &control::ControllerType) -> Result<(), ControllerError> {
+        match params {
+            control::ControllerType::Pid(config) => self.set_config(config.clone()),
+            _ => Err(ControllerError::ParamsMismatch(format!(
+                "expected PID parameters, got {:?}",
+                params
+            ))),
+        }
+    }
+
+    /// Sets the integral term so that the output continues from the handed over output.
+    fn take_over(&mut self, handover: &control::Handover) {
+        let config = &self.config;
+        self.current_signal = handover.output.clamp(config.output_min, config.output_max);
+        let p = match handover.measurement {
+            Some(measurement) => {
+                clamp_term(config.kp * (self.target - measurement), config.p_limit)
+            }
+            None => 0.0,
+        };
+        self.integral = clamp_term(self.current_signal - p, config.i_limit);
+        if let Some(measurement) = handover.measurement {
+            self.previous = Some((handover.timestamp, measurement));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Control;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn config(kp: f32, ki: f32, kd: f32) -> PidConfig {
+        serde_json::from_str(&format!(r#"{{"kp": {}, "ki": {}, "kd": {}}}"#, kp, ki, kd)).unwrap()
+    }
+
+    #[test]
+    fn test_zero_error_gives_zero_output() {
+        let mut controller = Controller::try_new(60.0, config(0.1, 0.0, 0.0)).unwrap();
+        assert_approx_eq!(controller.calculate_signal(Some(60.0), TimeStamp(0)), 0.0);
+        assert_approx_eq!(
+            controller.calculate_signal(Some(55.0), TimeStamp(1000)),
+            0.5
+        );
+        assert_approx_eq!(
+            controller.calculate_signal(Some(40.0), TimeStamp(2000)),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_integral_uses_elapsed_time() {
+        let mut controller = Controller::try_new(60.0, config(0.0, 0.01, 0.0)).unwrap();
+        controller.calculate_signal(Some(50.0), TimeStamp(0));
+        // The integral is updated after the output is computed.
+        assert_approx_eq!(
+            controller.calculate_signal(Some(50.0), TimeStamp(2000)),
+            0.0
+        );
+        assert_approx_eq!(
+            controller.calculate_signal(Some(50.0), TimeStamp(2500)),
+            0.2
+        );
+        assert_approx_eq!(
+            controller.calculate_signal(Some(50.0), TimeStamp(2500)),
+            0.25
+        );
+    }
+
+    #[test]
+    fn test_anti_windup() {
+        let mut clamp = config(0.0, 1.0, 0.0);
+        clamp.anti_windup = AntiWindup::Clamp;
+        clamp.i_limit = Some(1.0);
+        let conditional = config(0.0, 1.0, 0.0);
+        assert_eq!(conditional.anti_windup, AntiWindup::Conditional);
+        let mut back_calculation = config(0.0, 1.0, 0.0);
+        back_calculation.anti_windup = AntiWindup::BackCalculation { tracking_gain: 1.0 };
+        for config in [clamp, conditional, back_calculation] {
+            let mut controller = Controller::try_new(60.0, config).unwrap();
+            for t in 0..100 {
+                controller.calculate_signal(Some(20.0), TimeStamp(t * 1000));
+            }
+            // Without anti-windup the integral would reach 3960.
+            assert!(controller.integral < 50.0);
+            assert_approx_eq!(controller.current_signal, 1.0);
+        }
+    }
+
+    #[test]
+    fn test_take_over() {
+        let mut controller = Controller::try_new(60.0, config(0.1, 0.01, 0.0)).unwrap();
+        controller.take_over(&control::Handover {
+            output: 0.4,
+            measurement: Some(58.0),
+            timestamp: TimeStamp(0),
+        });
+        assert_approx_eq!(controller.integral, 0.2);
+        assert_approx_eq!(
+            controller.calculate_signal(Some(58.0), TimeStamp(1000)),
+            0.4
+        );
+    }
+
+    #[test]
+    fn test_set_params_keeps_integral() {
+        let mut controller = Controller::try_new(60.0, config(0.0, 0.01, 0.0)).unwrap();
+        controller.calculate_signal(Some(50.0), TimeStamp(0));
+        controller.calculate_signal(Some(50.0), TimeStamp(2000));
+        let params = control::ControllerType::Pid(config(0.05, 0.02, 0.0));
+        controller.set_params(&params).unwrap();
+        assert_approx_eq!(controller.integral, 0.2);
+        assert_approx_eq!(
+            controller.calculate_signal(Some(50.0), TimeStamp(2000)),
+            0.7
+        );
+        assert!(controller
+            .set_params(&control::ControllerType::Manual)
+            .is_err());
+    }
+
+    #[test]
+    fn test_pause_keeps_integral() {
+        let mut controller = Controller::try_new(60.0, config(0.0, 0.01, 0.0)).unwrap();
+        controller.calculate_signal(Some(50.0), TimeStamp(0));
+        controller.calculate_signal(Some(50.0), TimeStamp(2000));
+        controller.set_state(control::State::Inactive);
+        assert_approx_eq!(
+            controller.calculate_signal(Some(50.0), TimeStamp(60_000)),
+            0.0
+        );
+        controller.set_state(control::State::Active);
+        assert_approx_eq!(
+            controller.calculate_signal(Some(50.0), TimeStamp(120_000)),
+            0.2
+        );
+        assert_approx_eq!(controller.integral, 0.2);
+    }
+
+    #[test]
+    fn test_invalid_output_range() {
+        let mut config = config(1.0, 0.0, 0.0);
+        config.output_min = 1.0;
+        assert!(Controller::try_new(60.0, config).is_err());
+    }
 }
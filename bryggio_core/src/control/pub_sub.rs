@@ -1,17 +1,25 @@
 use crate::actor::pub_sub::SignalMsg;
 use crate::actor::ActorSignal;
+use crate::control::aggregate::{Aggregation, Aggregator};
+use crate::control::output::OutputMapping;
+use crate::control::ramp::{Ramp, TargetRamp};
 use crate::control::Control;
-use crate::control::ControllerType;
+use crate::control::{
+    AutotuneResult, ControllerConfig, ControllerError, ControllerType, Failsafe, Handover,
+    LoopStatus, State,
+};
 use crate::logger::{error, info};
 use crate::pub_sub::{
-    nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsClientConfig,
-    ClientId, PubSubClient, PubSubError, PubSubMsg, Subject,
+    heartbeat::Heartbeat, nats_client::decode_nats_data, nats_client::NatsClient,
+    nats_client::NatsClientConfig, ClientId, ClientState, PubSubClient, PubSubError, PubSubMsg,
+    Subject,
 };
 use crate::sensor::SensorMsg;
 use crate::supervisor::pub_sub::SupervisorPubMsg;
 use crate::time::{TimeStamp, LOOP_PAUSE_TIME};
 use nats::{Message, Subscription};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io::Write;
 use std::thread::sleep;
@@ -19,10 +27,31 @@ use std::thread::sleep;
 pub struct ControllerClient {
     id: ClientId,
     actor_id: ClientId,
+    /// All actors of the controller, see [`ControllerConfig::actor_ids`].
+    actor_ids: Vec<ClientId>,
+    output_mapping: Option<OutputMapping>,
     sensor_id: ClientId,
+    /// All sensors of the controller, see [`ControllerConfig::sensor_ids`].
+    sensor_ids: Vec<ClientId>,
     controller: Box<dyn Control>,
     client: NatsClient,
     type_: ControllerType,
+    failsafe: Option<Failsafe>,
+    aggregation: Option<Aggregation>,
+    /// Combines the main and aggregated sensors into the measurement of the controller.
+    aggregator: Aggregator,
+    /// Latest valid measurement of the controller, with its timestamp.
+    last_measurement: Option<(TimeStamp, f32)>,
+    /// Time of the latest valid measurement from each sensor.
+    last_meas: HashMap<ClientId, TimeStamp>,
+    /// Set while the failsafe output is used.
+    degraded: bool,
+    /// Latest published autotuning result, a restarted experiment publishes its new result.
+    published_autotune: Option<AutotuneResult>,
+    /// Set once the boil has been reported.
+    boil_published: bool,
+    /// Ramp towards a new target, if any.
+    ramp: Option<Ramp>,
 }
 
 impl PubSubClient for ControllerClient {
@@ -34,7 +63,16 @@ impl PubSubClient for ControllerClient {
             .subject(),
         )?;
         let controller = self.subscribe(&ControllerSubMsg::subject(&self.id))?;
-        let sensor = self.subscribe(&SensorMsg::subject(&self.sensor_id))?;
+        let ramp = self.subscribe(&ControllerSubMsg::ramp_subject(&self.id))?;
+        let handover = self.subscribe(&ControllerSubMsg::handover_subject(&self.id))?;
+        let params = self.subscribe(&ControllerSubMsg::params_subject(&self.id))?;
+        let pause = self.subscribe(&ControllerSubMsg::pause_subject(&self.id))?;
+        let resume = self.subscribe(&ControllerSubMsg::resume_subject(&self.id))?;
+        let mut sensors = Vec::new();
+        for sensor_id in self.sensor_ids.clone() {
+            let sensor = self.subscribe(&SensorMsg::subject(&sensor_id))?;
+            sensors.push((sensor_id, sensor));
+        }
         log_info(
             &self,
             &format!(
@@ -47,17 +85,33 @@ impl PubSubClient for ControllerClient {
             ),
         );
         self.status_update();
+        // Aggregated sensors count as one, through the main sensor.
+        let now = TimeStamp::now();
+        for (sensor_id, _) in sensors.iter() {
+            if sensor_id == &self.sensor_id || !self.aggregator.contains(sensor_id) {
+                self.last_meas.insert(sensor_id.clone(), now);
+            }
+        }
+        let mut heartbeat = Heartbeat::new("controller", &self.id);
         loop {
+            heartbeat.beat(&self, ClientState::Active);
             if let Some(msg) = kill_cmd.try_next() {
                 // TODO: Proper Status PubMsg.
                 println!("Got kill cmd");
                 let actor_msg = ControllerPubMsg::TurnOffActor;
-                let response = match self.client.request(
-                    &actor_msg.subject(&self.actor_id),
-                    &PubSubMsg("turn_off".into()),
-                ) {
-                    Ok(_) => format!("{}", self.controller.get_target()),
-                    Err(err) => format!("Failed turning actor off {}", err),
+                let failed = self
+                    .actor_ids
+                    .iter()
+                    .filter_map(|actor_id| {
+                        self.client
+                            .request(&actor_msg.subject(actor_id), &PubSubMsg("turn_off".into()))
+                            .err()
+                            .map(|err| format!("'{}': {}", actor_id, err))
+                    })
+                    .reduce(|acc, err| format!("{}, {}", acc, err));
+                let response = match failed {
+                    None => format!("{}", self.target()),
+                    Some(err) => format!("Failed turning actor off {}", err),
                 };
                 msg.respond(response).map_err(|err| PubSubError::Reply {
                     task: "kill contr.",
@@ -69,56 +123,143 @@ impl PubSubClient for ControllerClient {
                 return Ok(());
             }
 
-            if let Some(nats_msg) = controller.try_next() {
-                // TODO: Match and log error
+            // Like a kill, but the actor is left as is for the controller taking over.
+            if let Some(msg) = handover.try_next() {
+                let state = Handover {
+                    output: self.output(),
+                    measurement: self.last_measurement.map(|(_, meas)| meas),
+                    timestamp: self
+                        .last_measurement
+                        .map_or_else(TimeStamp::now, |(timestamp, _)| timestamp),
+                };
+                msg.respond(serde_json::to_string(&state).expect("Pub sub serialization error"))
+                    .map_err(|err| PubSubError::Reply {
+                        task: "hand over contr.",
+                        msg: msg.clone(),
+                        source: err,
+                    })?;
+                log_info(&self, "Contr. client handed over.");
+                return Ok(());
+            }
+
+            let commands = controller
+                .try_next()
+                .into_iter()
+                .chain(ramp.try_next())
+                .chain(params.try_next())
+                .chain(pause.try_next())
+                .chain(resume.try_next());
+            for nats_msg in commands {
                 match ControllerSubMsg::try_from(nats_msg.clone()) {
-                    Ok(msg) => match msg {
-                        ControllerSubMsg::SetTarget(new_target) => {
-                            let response = match self.controller.validate_target(new_target) {
-                                Ok(new_target) => {
-                                    self.controller.set_target(new_target);
-                                    log_info(
-                                        &self,
-                                        &format!(
-                                            "Setting target '{}' for controller '{}'",
-                                            new_target, self.id
-                                        ),
-                                    );
-                                    format!(
-                                        "Target '{}' set for controller '{}'",
-                                        new_target, self.id
-                                    )
-                                }
-                                Err(err) => err.to_string(),
-                            };
+                    Ok(msg) => {
+                        let response = match self.process_command(msg) {
+                            Ok(response) => response,
+                            Err(err) => err.to_string(),
+                        };
+                        // Programs publish new targets without waiting for a reply.
+                        if nats_msg.reply.is_some() {
                             nats_msg
                                 .respond(response)
                                 .map_err(|err| PubSubError::Reply {
-                                    task: "List active clients",
+                                    task: "set controller target",
                                     msg: nats_msg.clone(),
                                     source: err,
                                 })?;
                         }
-                    },
-                    Err(err) => log_error(&self, &err.to_string()),
+                    }
+                    Err(err) => {
+                        heartbeat.set_error(&err);
+                        log_error(&self, &err.to_string())
+                    }
                 };
                 self.status_update();
             }
 
-            if let Some(meas_msg) = sensor.try_next() {
-                if let Ok(msg) = SensorMsg::try_from(meas_msg) {
-                    self.controller.calculate_signal(msg.meas.ok());
+            if let Some(target_ramp) = &self.ramp {
+                let now = TimeStamp::now();
+                let effective_target = target_ramp.effective_target(now);
+                if effective_target != self.controller.get_target() {
+                    self.controller.set_target(effective_target);
+                }
+                if target_ramp.is_done(now) {
+                    self.ramp = None;
+                    log_info(
+                        &self,
+                        &format!("Ramp done, target '{}' reached", effective_target),
+                    );
+                    self.status_update();
+                }
+            }
+
+            for (sensor_id, sensor) in sensors.iter() {
+                if let Some(meas_msg) = sensor.try_next() {
+                    if let Ok(msg) = SensorMsg::try_from(meas_msg) {
+                        let valid = msg.meas.is_ok();
+                        if let Err(err) = &msg.meas {
+                            heartbeat.set_error(err);
+                        }
+                        if self.aggregator.contains(sensor_id) {
+                            // Staleness is judged on the receive time, the controller gets the
+                            // timestamp described in `Control::calculate_signal`.
+                            let now = TimeStamp::now();
+                            if self.aggregator.update(sensor_id, msg.meas.ok(), now) {
+                                let measurement = self.aggregator.value(now);
+                                let timestamp = match self.aggregator.combines_sensors() {
+                                    true => now,
+                                    false => msg.timestamp,
+                                };
+                                if let Some(measurement) = measurement {
+                                    self.last_measurement = Some((timestamp, measurement));
+                                    self.measurement_received(&self.sensor_id.clone());
+                                }
+                                self.controller.calculate_signal(measurement, timestamp);
+                            }
+                        } else {
+                            if msg.meas.is_ok() {
+                                self.measurement_received(sensor_id);
+                            }
+                            self.controller.secondary_measurement(
+                                sensor_id,
+                                msg.meas.ok(),
+                                msg.timestamp,
+                            );
+                        }
+                        if valid && !self.degraded {
+                            heartbeat.clear_error();
+                        }
+                    }
+                    if let Some(result) = self.controller.autotune_result() {
+                        if self.published_autotune.as_ref() != Some(&result) {
+                            self.publish_autotune_result(result);
+                        }
+                    }
+                    if !self.boil_published {
+                        if let Some(timestamp) = self.controller.boil_reached() {
+                            self.publish_boil_reached(timestamp);
+                        }
+                    }
+                    self.publish_signal()?;
+                    self.status_update();
+                }
+            }
+
+            if let Some(failsafe) = self.failsafe {
+                let stale_sensor = self.stale_sensor(&failsafe, TimeStamp::now());
+                if let (false, Some(sensor_id)) = (self.degraded, stale_sensor) {
+                    self.degraded = true;
+                    let err_msg = format!(
+                        "No valid measurement from sensor '{}' in {} ms, setting output to {}",
+                        sensor_id, failsafe.measurement_timeout_ms, failsafe.safe_output
+                    );
+                    heartbeat.set_error(&err_msg);
+                    log_error(&self, &err_msg);
+                    self.status_update();
+                }
+                // Sent on every loop, so that an actor restarted or reset while degraded also
+                // gets the safe output.
+                if self.degraded {
+                    self.publish_signal()?;
                 }
-                let msg = ControllerPubMsg::SetActorSignal(SignalMsg {
-                    id: self.actor_id.clone(),
-                    timestamp: TimeStamp::now(),
-                    signal: ActorSignal::new(
-                        self.actor_id.clone(),
-                        self.controller.get_control_signal(),
-                    ),
-                });
-                self.publish(&msg.subject(&self.actor_id), &msg.into())?;
-                self.status_update();
             }
             sleep(LOOP_PAUSE_TIME);
         }
@@ -135,21 +276,219 @@ impl PubSubClient for ControllerClient {
 
 impl ControllerClient {
     pub fn new(
-        id: ClientId,
-        actor_id: ClientId,
-        sensor_id: ClientId,
+        contr_config: &ControllerConfig,
         controller: Box<dyn Control>,
         config: &NatsClientConfig,
-        type_: ControllerType,
     ) -> Self {
         let client = NatsClient::try_new(config).unwrap();
         ControllerClient {
-            id,
-            actor_id,
-            sensor_id,
+            id: contr_config.controller_id.clone(),
+            actor_id: contr_config.actor_id.clone(),
+            actor_ids: contr_config.actor_ids().cloned().collect(),
+            output_mapping: contr_config.output.clone(),
+            sensor_id: contr_config.sensor_id.clone(),
+            sensor_ids: contr_config.sensor_ids().cloned().collect(),
             controller,
             client,
-            type_,
+            type_: contr_config.type_.clone(),
+            failsafe: contr_config.failsafe,
+            aggregation: contr_config.aggregation.clone(),
+            aggregator: Aggregator::new(&contr_config.sensor_id, contr_config.aggregation.as_ref()),
+            last_meas: HashMap::new(),
+            degraded: false,
+            published_autotune: None,
+            boil_published: false,
+            ramp: None,
+            last_measurement: None,
+        }
+    }
+
+    /// Final target, which differs from the target of the controller while ramping.
+    fn target(&self) -> f32 {
+        match &self.ramp {
+            Some(ramp) => ramp.target(),
+            None => self.controller.get_target(),
+        }
+    }
+
+    /// Process a target command, returns the reply.
+    fn process_command(&mut self, msg: ControllerSubMsg) -> Result<String, ControllerError> {
+        match msg {
+            ControllerSubMsg::SetTarget(new_target) => {
+                let new_target = self.controller.validate_target(new_target)?;
+                self.ramp = None;
+                self.controller.set_target(new_target);
+                log_info(
+                    self,
+                    &format!(
+                        "Setting target '{}' for controller '{}'",
+                        new_target, self.id
+                    ),
+                );
+                Ok(format!(
+                    "Target '{}' set for controller '{}'",
+                    new_target, self.id
+                ))
+            }
+            ControllerSubMsg::SetTargetRamp(target_ramp) => {
+                self.controller.validate_target(target_ramp.target)?;
+                let now = TimeStamp::now();
+                let mut ramp = Ramp::try_new(self.controller.get_target(), &target_ramp, now)?;
+                if self.controller.get_state() == State::Inactive {
+                    ramp.pause(now);
+                }
+                let response = format!(
+                    "Ramping controller '{}' to target '{}' over {} min",
+                    self.id,
+                    ramp.target(),
+                    ramp.duration_min()
+                );
+                log_info(self, &response);
+                self.ramp = Some(ramp);
+                Ok(response)
+            }
+            ControllerSubMsg::SetParams(params) => {
+                if !self.type_.same_type(&params) {
+                    return Err(ControllerError::ParamsMismatch(format!(
+                        "controller '{}' is {:?}, got {:?}",
+                        self.id, self.type_, params
+                    )));
+                }
+                if let Some(mapping) = &self.output_mapping {
+                    mapping.validate_output_range(&params)?;
+                }
+                self.controller.set_params(&params)?;
+                let response =
+                    format!("Parameters of controller '{}' set to {:?}", self.id, params);
+                log_info(self, &response);
+                self.type_ = params;
+                Ok(response)
+            }
+            ControllerSubMsg::Pause => {
+                self.controller.set_state(State::Inactive);
+                if let Some(ramp) = &mut self.ramp {
+                    ramp.pause(TimeStamp::now());
+                }
+                // Turned off again with the next measurement if this fails.
+                let response = match self.publish_signal() {
+                    Ok(()) => format!("Controller '{}' paused", self.id),
+                    Err(err) => format!(
+                        "Controller '{}' paused, failed turning actors off: {}",
+                        self.id, err
+                    ),
+                };
+                log_info(self, &response);
+                Ok(response)
+            }
+            ControllerSubMsg::Resume => {
+                self.controller.set_state(State::Active);
+                if let Some(ramp) = &mut self.ramp {
+                    ramp.resume(TimeStamp::now());
+                }
+                let response = format!("Controller '{}' resumed", self.id);
+                log_info(self, &response);
+                Ok(response)
+            }
+        }
+    }
+
+    /// Publish the autotuning result, with a PID config ready for `command.switch_controller`.
+    fn publish_autotune_result(&mut self, result: AutotuneResult) {
+        log_info(
+            self,
+            &format!(
+                "Autotuning done, ultimate gain {}, ultimate period {} s",
+                result.ultimate_gain, result.ultimate_period_s
+            ),
+        );
+        let msg = ControllerPubMsg::Autotune {
+            config: ControllerConfig {
+                controller_id: self.id.clone(),
+                actor_id: self.actor_id.clone(),
+                sensor_id: self.sensor_id.clone(),
+                type_: ControllerType::Pid(result.pid.clone()),
+                failsafe: self.failsafe,
+                aggregation: self.aggregation.clone(),
+                output: self.output_mapping.clone(),
+            },
+            result: result.clone(),
+        };
+        match self.publish(&msg.subject(&self.id), &msg.into()) {
+            Ok(()) => self.published_autotune = Some(result),
+            Err(err) => log_error(self, &format!("Could not publish autotune result: {}", err)),
+        }
+    }
+
+    fn publish_boil_reached(&mut self, timestamp: TimeStamp) {
+        log_info(
+            self,
+            &format!("Boil reached, target '{}'", self.controller.get_target()),
+        );
+        let msg = ControllerPubMsg::BoilReached {
+            id: self.id.clone(),
+            timestamp,
+        };
+        match self.publish(&msg.subject(&self.id), &msg.into()) {
+            Ok(()) => self.boil_published = true,
+            Err(err) => log_error(self, &format!("Could not publish boil reached: {}", err)),
+        }
+    }
+
+    fn measurement_received(&mut self, sensor_id: &ClientId) {
+        self.last_meas.insert(sensor_id.clone(), TimeStamp::now());
+        let recovered = self
+            .failsafe
+            .is_none_or(|failsafe| self.stale_sensor(&failsafe, TimeStamp::now()).is_none());
+        if self.degraded && recovered {
+            self.degraded = false;
+            log_info(self, "Measurements are back, resuming control");
+        }
+    }
+
+    /// A sensor without valid measurements for longer than the failsafe timeout.
+    fn stale_sensor(&self, failsafe: &Failsafe, now: TimeStamp) -> Option<ClientId> {
+        self.last_meas
+            .iter()
+            .find(|(_, last_meas)| failsafe.expired(**last_meas, now))
+            .map(|(sensor_id, _)| sensor_id.clone())
+    }
+
+    /// Signal sent to the actor, zero while paused and the failsafe output while degraded.
+    fn output(&self) -> f32 {
+        if self.controller.get_state() == State::Inactive {
+            return 0.0;
+        }
+        match (self.degraded, self.failsafe) {
+            (true, Some(failsafe)) => failsafe.safe_output,
+            _ => self.controller.get_control_signal(),
+        }
+    }
+
+    /// Publish the output to each actor, split according to the output mapping.
+    fn publish_signal(&self) -> Result<(), PubSubError> {
+        let signals = match &self.output_mapping {
+            Some(mapping) => mapping.split(self.output()),
+            None => vec![self.output()],
+        };
+        // Every actor gets its signal, even if publishing to another one fails.
+        let failures: Vec<String> = self
+            .actor_ids
+            .iter()
+            .zip(signals)
+            .filter_map(|(actor_id, signal)| {
+                let msg = ControllerPubMsg::SetActorSignal(SignalMsg {
+                    id: actor_id.clone(),
+                    timestamp: TimeStamp::now(),
+                    signal: ActorSignal::new(actor_id.clone(), signal),
+                });
+                self.publish(&msg.subject(actor_id), &msg.into())
+                    .err()
+                    .map(|err| format!("actor '{}': {}", actor_id, err))
+            })
+            .collect();
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(PubSubError::Publish(failures.join(", "))),
         }
     }
 
@@ -157,8 +496,12 @@ impl ControllerClient {
         let status_update = ControllerPubMsg::Status {
             id: self.id.clone(),
             timestamp: TimeStamp::now(),
-            target: self.controller.get_target(),
+            target: self.target(),
+            effective_target: Some(self.controller.get_target()),
+            state: self.controller.get_state(),
             type_: self.type_.clone(),
+            degraded: self.degraded,
+            loops: self.controller.loop_status(),
         };
         if let Err(err) = self.publish(&status_update.subject(&self.id), &status_update.into()) {
             log_error(self, &format!("Could not publish status update: {}", err));
@@ -194,17 +537,62 @@ fn log_error(client: &ControllerClient, msg: &str) {
 pub enum ControllerSubMsg {
     #[serde(rename = "set_target")]
     SetTarget(f32),
+    #[serde(rename = "set_target_ramp")]
+    SetTargetRamp(TargetRamp),
+    /// New parameters, of the same type as the running controller.
+    #[serde(rename = "set_params")]
+    SetParams(ControllerType),
+    /// Turn the actors off and keep the controller state until resumed.
+    #[serde(rename = "pause")]
+    Pause,
+    #[serde(rename = "resume")]
+    Resume,
 }
 
 impl ControllerSubMsg {
     pub fn subject(id: &ClientId) -> Subject {
         Subject(format!("controller.{}.set_target", id))
     }
+
+    pub fn ramp_subject(id: &ClientId) -> Subject {
+        Subject(format!("controller.{}.set_target_ramp", id))
+    }
+
+    pub fn pause_subject(id: &ClientId) -> Subject {
+        Subject(format!("controller.{}.pause", id))
+    }
+
+    pub fn resume_subject(id: &ClientId) -> Subject {
+        Subject(format!("controller.{}.resume", id))
+    }
+
+    pub fn params_subject(id: &ClientId) -> Subject {
+        Subject(format!("controller.{}.set_params", id))
+    }
+
+    /// Stops the controller without turning off its actors, the reply is a [`Handover`].
+    pub fn handover_subject(id: &ClientId) -> Subject {
+        Subject(format!("controller.{}.handover", id))
+    }
 }
 
 impl TryFrom<Message> for ControllerSubMsg {
     type Error = PubSubError;
     fn try_from(msg: Message) -> Result<Self, Self::Error> {
+        if msg.subject.ends_with(".set_target_ramp") {
+            let ramp: TargetRamp = decode_nats_data(&msg.data)?;
+            return Ok(ControllerSubMsg::SetTargetRamp(ramp));
+        }
+        if msg.subject.ends_with(".pause") {
+            return Ok(ControllerSubMsg::Pause);
+        }
+        if msg.subject.ends_with(".resume") {
+            return Ok(ControllerSubMsg::Resume);
+        }
+        if msg.subject.ends_with(".set_params") {
+            let params: ControllerType = decode_nats_data(&msg.data)?;
+            return Ok(ControllerSubMsg::SetParams(params));
+        }
         let new_target: f32 = decode_nats_data(&msg.data)?;
         Ok(ControllerSubMsg::SetTarget(new_target))
     }
@@ -214,6 +602,13 @@ impl From<ControllerSubMsg> for PubSubMsg {
     fn from(msg: ControllerSubMsg) -> PubSubMsg {
         match msg {
             ControllerSubMsg::SetTarget(new_target) => PubSubMsg(new_target.to_string()),
+            ControllerSubMsg::SetTargetRamp(ramp) => {
+                PubSubMsg(serde_json::to_string(&ramp).expect("Pub sub serialization error"))
+            }
+            ControllerSubMsg::SetParams(params) => {
+                PubSubMsg(serde_json::to_string(&params).expect("Pub sub serialization error"))
+            }
+            ControllerSubMsg::Pause | ControllerSubMsg::Resume => PubSubMsg::empty(),
         }
     }
 }
@@ -229,10 +624,30 @@ pub enum ControllerPubMsg {
     Status {
         id: ClientId,
         timestamp: TimeStamp,
+        /// Final target, see `effective_target` for the target currently used.
         target: f32,
+        /// Target currently used, differs from `target` while ramping.
+        #[serde(default)]
+        effective_target: Option<f32>,
+        /// Inactive while paused.
+        #[serde(default)]
+        state: State,
         #[serde(rename = "type")]
         type_: ControllerType,
+        /// Set while measurements are missing and the failsafe output is used.
+        #[serde(default)]
+        degraded: bool,
+        /// Per loop state, only for controllers with several loops.
+        #[serde(default)]
+        loops: Vec<LoopStatus>,
+    },
+    #[serde(rename = "autotune")]
+    Autotune {
+        result: AutotuneResult,
+        config: ControllerConfig,
     },
+    #[serde(rename = "boil_reached")]
+    BoilReached { id: ClientId, timestamp: TimeStamp },
 }
 
 impl ControllerPubMsg {
@@ -244,12 +659,13 @@ impl ControllerPubMsg {
                 signal: _,
             }) => Subject(format!("actor.{}.set_signal", msg_id)),
             ControllerPubMsg::TurnOffActor => Subject(format!("actor.{}.turn_off", msg_id)),
-            ControllerPubMsg::Status {
-                id,
-                timestamp: _,
-                target: _,
-                type_: _,
-            } => Subject(format!("controller.{}.status", id)),
+            ControllerPubMsg::Status { id, .. } => Subject(format!("controller.{}.status", id)),
+            ControllerPubMsg::Autotune { config, .. } => {
+                Subject(format!("controller.{}.autotune", config.controller_id))
+            }
+            ControllerPubMsg::BoilReached { id, .. } => {
+                Subject(format!("controller.{}.boil_reached", id))
+            }
         }
     }
 }
@@ -0,0 +1,151 @@
+//! Target ramps
+//!
+//! A ramp moves the target of a controller linearly from its current value to a new one,
+//! either at a given rate or over a given time.
+//! The ramp is run by [`ControllerClient`](super::ControllerClient), which sets the effective
+//! target of the controller on every iteration, so that it works with any controller type.
+//! While the controller is paused the ramp is paused as well, it continues where it left off.
+use crate::control::ControllerError;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+
+/// Ramp request, sent on `controller.<id>.set_target_ramp`.
+///
+/// Exactly one of `rate_per_min` and `duration_min` must be set.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TargetRamp {
+    pub target: f32,
+    /// Rate of change of the target, in degrees per minute.
+    #[serde(default)]
+    pub rate_per_min: Option<f32>,
+    #[serde(default)]
+    pub duration_min: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ramp {
+    from: f32,
+    target: f32,
+    started: TimeStamp,
+    duration_ms: u128,
+    /// Time the ramp was paused, if it is.
+    paused: Option<TimeStamp>,
+}
+
+impl Ramp {
+    pub fn try_new(from: f32, ramp: &TargetRamp, now: TimeStamp) -> Result<Ramp, ControllerError> {
+        let duration_min = match (ramp.rate_per_min, ramp.duration_min) {
+            (Some(rate), None) if rate > 0.0 => (ramp.target - from).abs() / rate,
+            (None, Some(duration)) if duration >= 0.0 => duration,
+            _ => {
+                return Err(ControllerError::ParamError(String::from(
+                    "Set either a positive rate_per_min or a non-negative duration_min",
+                )))
+            }
+        };
+        Ok(Ramp {
+            from,
+            target: ramp.target,
+            started: now,
+            duration_ms: (duration_min * 60_000.0) as u128,
+            paused: None,
+        })
+    }
+
+    /// Final target of the ramp.
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn duration_min(&self) -> f32 {
+        self.duration_ms as f32 / 60_000.0
+    }
+
+    pub fn effective_target(&self, now: TimeStamp) -> f32 {
+        if self.is_done(now) {
+            return self.target;
+        }
+        let progress = self.elapsed_ms(now) as f32 / self.duration_ms as f32;
+        self.from + (self.target - self.from) * progress
+    }
+
+    pub fn is_done(&self, now: TimeStamp) -> bool {
+        self.elapsed_ms(now) >= self.duration_ms
+    }
+
+    /// Hold the effective target until the ramp is resumed.
+    pub fn pause(&mut self, now: TimeStamp) {
+        if self.paused.is_none() {
+            self.paused = Some(now);
+        }
+    }
+
+    /// Continue from where the ramp was paused, the pause is added to the start time.
+    pub fn resume(&mut self, now: TimeStamp) {
+        if let Some(paused) = self.paused.take() {
+            self.started = self.started + TimeStamp(now.0.saturating_sub(paused.0));
+        }
+    }
+
+    /// Time spent ramping, not counting the current pause.
+    fn elapsed_ms(&self, now: TimeStamp) -> u128 {
+        let now = self.paused.unwrap_or(now);
+        now.0.saturating_sub(self.started.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_ramp_rate() {
+        let request: TargetRamp =
+            serde_json::from_str(r#"{"target": 72.0, "rate_per_min": 1.0}"#).unwrap();
+        let ramp = Ramp::try_new(64.0, &request, TimeStamp(0)).unwrap();
+        assert_approx_eq!(ramp.duration_min(), 8.0);
+        assert_approx_eq!(ramp.effective_target(TimeStamp(0)), 64.0);
+        assert_approx_eq!(ramp.effective_target(TimeStamp(2 * 60_000)), 66.0);
+        assert!(!ramp.is_done(TimeStamp(2 * 60_000)));
+        assert_approx_eq!(ramp.effective_target(TimeStamp(10 * 60_000)), 72.0);
+        assert!(ramp.is_done(TimeStamp(8 * 60_000)));
+    }
+
+    #[test]
+    fn test_ramp_duration_down() {
+        let request: TargetRamp =
+            serde_json::from_str(r#"{"target": 18.0, "duration_min": 60}"#).unwrap();
+        let ramp = Ramp::try_new(20.0, &request, TimeStamp(1000)).unwrap();
+        assert_approx_eq!(ramp.effective_target(TimeStamp(1000 + 30 * 60_000)), 19.0);
+    }
+
+    #[test]
+    fn test_ramp_pause() {
+        let request: TargetRamp =
+            serde_json::from_str(r#"{"target": 72.0, "rate_per_min": 1.0}"#).unwrap();
+        let mut ramp = Ramp::try_new(64.0, &request, TimeStamp(0)).unwrap();
+        ramp.pause(TimeStamp(2 * 60_000));
+        assert_approx_eq!(ramp.effective_target(TimeStamp(30 * 60_000)), 66.0);
+        assert!(!ramp.is_done(TimeStamp(30 * 60_000)));
+        ramp.resume(TimeStamp(30 * 60_000));
+        assert_approx_eq!(ramp.effective_target(TimeStamp(31 * 60_000)), 67.0);
+        assert!(ramp.is_done(TimeStamp(36 * 60_000)));
+    }
+
+    #[test]
+    fn test_ramp_invalid() {
+        let both = TargetRamp {
+            target: 20.0,
+            rate_per_min: Some(1.0),
+            duration_min: Some(1.0),
+        };
+        assert!(Ramp::try_new(18.0, &both, TimeStamp(0)).is_err());
+        let negative = TargetRamp {
+            target: 20.0,
+            rate_per_min: Some(-1.0),
+            duration_min: None,
+        };
+        assert!(Ramp::try_new(18.0, &negative, TimeStamp(0)).is_err());
+    }
+}
@@ -3,19 +3,20 @@ use std::path::PathBuf;
 use std::thread::sleep;
 
 use crate::actor::pub_sub::{actor_current_signal_subject, SignalMsg};
-use crate::pub_sub::ClientId;
 use crate::pub_sub::{
-    nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsClientConfig,
-    PubSubClient, PubSubError, PubSubMsg, Subject,
+    heartbeat::Heartbeat, nats_client::decode_nats_data, nats_client::NatsClient,
+    nats_client::NatsClientConfig, PubSubClient, PubSubError, PubSubMsg, Subject,
 };
+use crate::pub_sub::{ClientId, ClientState};
 use crate::sensor::SensorMsg;
+use crate::supervisor::pub_sub::SupervisorPubMsg;
 use crate::time::{TimeStamp, LOOP_PAUSE_TIME};
 use csv::WriterBuilder;
 use nats::Subscription;
 use serde::{Deserialize, Serialize};
 
 pub struct DataLogger {
-    _id: ClientId,
+    id: ClientId,
     client: NatsClient,
     log_file_path: PathBuf,
 }
@@ -29,7 +30,24 @@ impl PubSubClient for DataLogger {
         let wildcard_id = ClientId::from("*");
         let sensor_sub = self.subscribe(&Subject(String::from("sensor.*.measurement")))?;
         let actor_sub = self.subscribe(&actor_current_signal_subject(&wildcard_id))?;
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: self.id.clone(),
+            }
+            .subject(),
+        )?;
+        let mut heartbeat = Heartbeat::new("data_logger", &self.id);
         loop {
+            heartbeat.beat(&self, ClientState::Active);
+            if let Some(msg) = kill_cmd.try_next() {
+                msg.respond(serde_json::to_string(&()).expect("Can always serialize"))
+                    .map_err(|err| PubSubError::Reply {
+                        task: "kill data logger",
+                        msg: msg.clone(),
+                        source: err,
+                    })?;
+                return Ok(());
+            }
             if let Some(msg) = sensor_sub.try_next() {
                 let data = decode_nats_data::<SensorMsg>(&msg.data).expect("Failed decoding data");
                 let rec_str = Record::from(data);
@@ -59,7 +77,7 @@ impl DataLogger {
     pub fn new(id: ClientId, config: &NatsClientConfig, log_file_path: PathBuf) -> Self {
         let client = NatsClient::try_new(config).unwrap();
         Self {
-            _id: id,
+            id,
             client,
             log_file_path,
         }
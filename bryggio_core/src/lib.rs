@@ -36,8 +36,10 @@ pub mod control;
 pub mod data_logger;
 mod hardware;
 pub mod logger;
+pub mod program;
 pub mod pub_sub;
+pub mod safety;
 pub mod sensor;
 pub mod supervisor;
-mod time;
+pub mod time;
 pub mod utils;
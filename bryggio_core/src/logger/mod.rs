@@ -1,9 +1,12 @@
 use crate::pub_sub::{
-    nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsClientConfig,
-    MessageParseError, PubSubClient, PubSubError, PubSubMsg, Subject,
+    heartbeat::Heartbeat, nats_client::decode_nats_data, nats_client::NatsClient,
+    nats_client::NatsClientConfig, ClientId, ClientState, MessageParseError, PubSubClient,
+    PubSubError, PubSubMsg, Subject,
 };
+use crate::supervisor::pub_sub::SupervisorPubMsg;
+use crate::time::LOOP_PAUSE_TIME;
 use derive_more::{Display, From};
-use nats::Subscription;
+use nats::{Message, Subscription};
 use serde::{Deserialize, Serialize};
 
 pub fn debug<T: Into<LogMsg>, C: PubSubClient>(client: &C, msg: T, sub_subject: &str) {
@@ -14,7 +17,7 @@ pub fn info<T: Into<LogMsg>, C: PubSubClient>(client: &C, msg: T, sub_subject: &
     log(client, msg, sub_subject, LogLevel::Info);
 }
 
-pub fn _warning<T: Into<LogMsg>, C: PubSubClient>(client: &C, msg: T, sub_subject: &str) {
+pub fn warning<T: Into<LogMsg>, C: PubSubClient>(client: &C, msg: T, sub_subject: &str) {
     log(client, msg, sub_subject, LogLevel::Warning);
 }
 
@@ -48,15 +51,31 @@ pub struct Log {
 impl PubSubClient for Log {
     fn client_loop(self) -> Result<(), PubSubError> {
         let log_sub = self.subscribe(&Subject(String::from("log.>")))?;
+        let id = ClientId::from("log");
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: id.clone(),
+            }
+            .subject(),
+        )?;
+        let mut heartbeat = Heartbeat::new("logger", &id);
         loop {
-            if let Some(msg) = log_sub.next() {
-                match LogLevel::from_msg_subject(&msg.subject) {
-                    Ok(log_level) => match decode_nats_data::<LogMsg>(&msg.data) {
-                        Ok(msg) => self.log(&msg.0, log_level),
-                        Err(err) => self.error(&err.to_string()),
-                    },
-                    Err(err) => self.error(&err.to_string()),
-                };
+            heartbeat.beat(&self, ClientState::Active);
+            if let Some(msg) = kill_cmd.try_next() {
+                // Drain the remaining log messages before exiting.
+                for pending in log_sub.try_iter() {
+                    self.log_msg(&pending);
+                }
+                msg.respond(serde_json::to_string(&()).expect("Can always serialize"))
+                    .map_err(|err| PubSubError::Reply {
+                        task: "kill log",
+                        msg: msg.clone(),
+                        source: err,
+                    })?;
+                return Ok(());
+            }
+            if let Ok(msg) = log_sub.next_timeout(LOOP_PAUSE_TIME) {
+                self.log_msg(&msg);
             }
         }
     }
@@ -76,6 +95,16 @@ impl Log {
         Log { level, client }
     }
 
+    fn log_msg(&self, msg: &Message) {
+        match LogLevel::from_msg_subject(&msg.subject) {
+            Ok(log_level) => match decode_nats_data::<LogMsg>(&msg.data) {
+                Ok(msg) => self.log(&msg.0, log_level),
+                Err(err) => self.error(&err.to_string()),
+            },
+            Err(err) => self.error(&err.to_string()),
+        };
+    }
+
     pub fn log(&self, msg: &str, level: LogLevel) {
         match level {
             LogLevel::Debug => self.debug(msg),
@@ -0,0 +1,404 @@
+//! Brew programs
+//!
+//! A brew program, e.g. a step mash, is an ordered list of steps.
+//! Each step sets new targets for one or more controllers and holds them for a given time.
+//! The hold timer of a step only starts once the sensors of all its controllers have reached
+//! their targets.
+//! Only readings received since the step started, and no older than `max_staleness_ms`,
+//! count towards reaching a target.
+//!
+//! The program logic lives in [`ProgramRunner`], which is driven by the pub-sub client
+//! [`ProgramClient`].
+use crate::pub_sub::ClientId;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+pub mod pub_sub;
+pub use pub_sub::ProgramClient;
+
+/// Program definition, typically read from JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProgramConfig {
+    pub program_id: ClientId,
+    /// Controllers driven by the program.
+    pub controllers: Vec<ProgramController>,
+    pub steps: Vec<Step>,
+}
+
+/// A controller driven by a program, with the sensor used to tell if a step target is reached.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProgramController {
+    pub controller_id: ClientId,
+    pub sensor_id: ClientId,
+    /// Maximum distance from the target for it to count as reached.
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f32,
+    /// Maximum age of a reading for it to count towards reaching a target.
+    #[serde(default = "default_max_staleness_ms")]
+    pub max_staleness_ms: u64,
+}
+
+fn default_tolerance() -> f32 {
+    0.5
+}
+
+fn default_max_staleness_ms() -> u64 {
+    10_000
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Step {
+    pub name: String,
+    /// New target for each controller, controllers not listed keep their current target.
+    pub targets: HashMap<ClientId, f32>,
+    /// Time to hold the targets, once they are reached.
+    pub hold_min: f32,
+}
+
+impl Step {
+    fn hold_ms(&self) -> u128 {
+        (self.hold_min * 60_000.0) as u128
+    }
+}
+
+impl ProgramConfig {
+    pub fn validate(&self) -> Result<(), ProgramError> {
+        if self.steps.is_empty() {
+            return Err(ProgramError::Config(String::from("Program has no steps")));
+        }
+        for step in &self.steps {
+            if step.hold_min < 0.0 {
+                return Err(ProgramError::Config(format!(
+                    "Negative hold time in step '{}'",
+                    step.name
+                )));
+            }
+            if let Some(id) = step.targets.keys().find(|id| self.controller(id).is_none()) {
+                return Err(ProgramError::Config(format!(
+                    "Step '{}' targets controller '{}' which is not part of the program",
+                    step.name, id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn controller_ids(&self) -> impl Iterator<Item = &ClientId> {
+        self.controllers.iter().map(|contr| &contr.controller_id)
+    }
+
+    fn controller(&self, id: &ClientId) -> Option<&ProgramController> {
+        self.controllers
+            .iter()
+            .find(|contr| &contr.controller_id == id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    /// Waiting for the sensors to reach the step targets.
+    #[serde(rename = "ramping")]
+    Ramping,
+    #[serde(rename = "holding")]
+    Holding,
+    #[serde(rename = "finished")]
+    Finished,
+    #[serde(rename = "aborted")]
+    Aborted,
+}
+
+/// Progress of a program, published on `program.<id>.progress`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProgramProgress {
+    pub id: ClientId,
+    pub timestamp: TimeStamp,
+    pub step: usize,
+    pub step_count: usize,
+    pub step_name: String,
+    pub phase: Phase,
+    pub paused: bool,
+    pub hold_elapsed_ms: u128,
+    pub hold_remaining_ms: u128,
+}
+
+/// Controller targets that should be set.
+pub type Targets = HashMap<ClientId, f32>;
+
+/// State machine stepping through a program.
+///
+/// Pausing freezes the hold timer, the controllers keep their current targets.
+/// Neither finishing nor aborting a program stops its controllers.
+#[derive(Debug, Clone)]
+pub struct ProgramRunner {
+    config: ProgramConfig,
+    step: usize,
+    phase: Phase,
+    paused: bool,
+    hold_elapsed_ms: u128,
+    last_update: TimeStamp,
+    /// Time the current step was entered.
+    step_started: TimeStamp,
+    /// Latest measurement from each sensor, with the time it was received.
+    measurements: HashMap<ClientId, (TimeStamp, f32)>,
+}
+
+impl ProgramRunner {
+    pub fn try_new(config: ProgramConfig) -> Result<Self, ProgramError> {
+        config.validate()?;
+        Ok(ProgramRunner {
+            config,
+            step: 0,
+            phase: Phase::Ramping,
+            paused: false,
+            hold_elapsed_ms: 0,
+            last_update: TimeStamp::now(),
+            step_started: TimeStamp::now(),
+            measurements: HashMap::new(),
+        })
+    }
+
+    pub fn id(&self) -> &ClientId {
+        &self.config.program_id
+    }
+
+    pub fn config(&self) -> &ProgramConfig {
+        &self.config
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.phase, Phase::Finished | Phase::Aborted)
+    }
+
+    /// Start the first step, returns its targets.
+    pub fn start(&mut self, now: TimeStamp) -> Targets {
+        self.last_update = now;
+        self.enter_step(0, now)
+    }
+
+    /// Record a valid measurement, `now` is the time it was received.
+    pub fn update_measurement(&mut self, sensor_id: &ClientId, meas: f32, now: TimeStamp) {
+        self.measurements.insert(sensor_id.clone(), (now, meas));
+    }
+
+    /// Advance the program, returns the targets of the next step if a new step was entered.
+    pub fn tick(&mut self, now: TimeStamp) -> Option<Targets> {
+        let elapsed = now.0.saturating_sub(self.last_update.0);
+        self.last_update = now;
+        if self.paused || self.is_done() {
+            return None;
+        }
+        if self.phase == Phase::Ramping && self.targets_reached(now) {
+            self.phase = Phase::Holding;
+            return None;
+        }
+        if self.phase == Phase::Holding {
+            self.hold_elapsed_ms += elapsed;
+            if self.hold_elapsed_ms >= self.current_step().hold_ms() {
+                return self.next_step(now);
+            }
+        }
+        None
+    }
+
+    pub fn pause(&mut self) -> Result<(), ProgramError> {
+        self.ensure_running()?;
+        self.paused = true;
+        Ok(())
+    }
+
+    pub fn resume(&mut self, now: TimeStamp) -> Result<(), ProgramError> {
+        self.ensure_running()?;
+        // The time spent paused does not count towards the hold time.
+        self.last_update = now;
+        self.paused = false;
+        Ok(())
+    }
+
+    /// Skip the rest of the current step, returns the targets of the next step.
+    pub fn skip(&mut self, now: TimeStamp) -> Result<Option<Targets>, ProgramError> {
+        self.ensure_running()?;
+        Ok(self.next_step(now))
+    }
+
+    pub fn abort(&mut self) -> Result<(), ProgramError> {
+        self.ensure_running()?;
+        self.phase = Phase::Aborted;
+        Ok(())
+    }
+
+    pub fn progress(&self, now: TimeStamp) -> ProgramProgress {
+        let step = self.current_step();
+        ProgramProgress {
+            id: self.config.program_id.clone(),
+            timestamp: now,
+            step: self.step,
+            step_count: self.config.steps.len(),
+            step_name: step.name.clone(),
+            phase: self.phase,
+            paused: self.paused,
+            hold_elapsed_ms: self.hold_elapsed_ms,
+            hold_remaining_ms: step.hold_ms().saturating_sub(self.hold_elapsed_ms),
+        }
+    }
+
+    fn current_step(&self) -> &Step {
+        &self.config.steps[self.step]
+    }
+
+    fn next_step(&mut self, now: TimeStamp) -> Option<Targets> {
+        if self.step + 1 < self.config.steps.len() {
+            Some(self.enter_step(self.step + 1, now))
+        } else {
+            self.phase = Phase::Finished;
+            None
+        }
+    }
+
+    fn enter_step(&mut self, step: usize, now: TimeStamp) -> Targets {
+        self.step = step;
+        self.phase = Phase::Ramping;
+        self.hold_elapsed_ms = 0;
+        self.step_started = now;
+        self.current_step().targets.clone()
+    }
+
+    fn targets_reached(&self, now: TimeStamp) -> bool {
+        self.current_step()
+            .targets
+            .iter()
+            .all(|(contr_id, target)| {
+                self.config
+                    .controller(contr_id)
+                    .and_then(|contr| {
+                        self.measurements
+                            .get(&contr.sensor_id)
+                            .filter(|(received, _)| {
+                                *received > self.step_started
+                                    && now.0.saturating_sub(received.0)
+                                        <= u128::from(contr.max_staleness_ms)
+                            })
+                            .map(|(_, meas)| (meas - target).abs() <= contr.tolerance)
+                    })
+                    .unwrap_or(false)
+            })
+    }
+
+    fn ensure_running(&self) -> Result<(), ProgramError> {
+        match self.is_done() {
+            true => Err(ProgramError::NotRunning(self.config.program_id.clone())),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ProgramError {
+    #[error("Program config error: {0}")]
+    Config(String),
+    #[error("Program '{0}' is not running")]
+    NotRunning(ClientId),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STEP_MASH: &str = r#"
+        {
+          "program_id": "step_mash",
+          "controllers": [{"controller_id": "mash", "sensor_id": "mash_temp"}],
+          "steps": [
+            {"name": "protein rest", "targets": {"mash": 52.0}, "hold_min": 15},
+            {"name": "saccharification", "targets": {"mash": 64.0}, "hold_min": 45},
+            {"name": "mash out", "targets": {"mash": 78.0}, "hold_min": 0}
+          ]
+        }"#;
+
+    fn step_mash() -> ProgramRunner {
+        ProgramRunner::try_new(serde_json::from_str(STEP_MASH).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_hold_starts_when_target_reached() {
+        let mut runner = step_mash();
+        let sensor = ClientId::from("mash_temp");
+        let targets = runner.start(TimeStamp(0));
+        assert_eq!(targets.get(&ClientId::from("mash")), Some(&52.0));
+
+        runner.update_measurement(&sensor, 40.0, TimeStamp(60_000));
+        assert_eq!(runner.tick(TimeStamp(60_000)), None);
+        assert_eq!(runner.progress(TimeStamp(60_000)).phase, Phase::Ramping);
+
+        runner.update_measurement(&sensor, 51.8, TimeStamp(120_000));
+        runner.tick(TimeStamp(120_000));
+        assert_eq!(runner.progress(TimeStamp(120_000)).phase, Phase::Holding);
+        assert_eq!(runner.tick(TimeStamp(120_000 + 14 * 60_000)), None);
+
+        let targets = runner.tick(TimeStamp(120_000 + 15 * 60_000)).unwrap();
+        assert_eq!(targets.get(&ClientId::from("mash")), Some(&64.0));
+        assert_eq!(runner.progress(TimeStamp(0)).step, 1);
+    }
+
+    #[test]
+    fn test_pause_freezes_hold_timer() {
+        let mut runner = step_mash();
+        runner.start(TimeStamp(0));
+        runner.update_measurement(&ClientId::from("mash_temp"), 52.0, TimeStamp(1000));
+        runner.tick(TimeStamp(1000));
+        runner.tick(TimeStamp(61_000));
+        runner.pause().unwrap();
+        runner.tick(TimeStamp(30 * 60_000));
+        runner.resume(TimeStamp(30 * 60_000)).unwrap();
+        runner.tick(TimeStamp(31 * 60_000));
+        assert_eq!(runner.progress(TimeStamp(0)).hold_elapsed_ms, 2 * 60_000);
+    }
+
+    #[test]
+    fn test_skip_and_abort() {
+        let mut runner = step_mash();
+        runner.start(TimeStamp(0));
+        runner.skip(TimeStamp(0)).unwrap();
+        runner.skip(TimeStamp(0)).unwrap();
+        assert_eq!(runner.skip(TimeStamp(0)), Ok(None));
+        assert!(runner.is_done());
+        assert!(runner.abort().is_err());
+
+        let mut runner = step_mash();
+        runner.start(TimeStamp(0));
+        runner.abort().unwrap();
+        assert_eq!(runner.progress(TimeStamp(0)).phase, Phase::Aborted);
+        assert_eq!(runner.tick(TimeStamp(1000)), None);
+    }
+
+    #[test]
+    fn test_only_fresh_readings_count() {
+        let mut runner = step_mash();
+        let sensor = ClientId::from("mash_temp");
+        runner.start(TimeStamp(0));
+        // Received before the step started.
+        runner.update_measurement(&sensor, 64.0, TimeStamp(0));
+        runner.skip(TimeStamp(1000)).unwrap();
+        runner.tick(TimeStamp(2000));
+        assert_eq!(runner.progress(TimeStamp(2000)).phase, Phase::Ramping);
+
+        // Too old, e.g. from a sensor that has since died.
+        runner.update_measurement(&sensor, 64.0, TimeStamp(3000));
+        runner.tick(TimeStamp(13_001));
+        assert_eq!(runner.progress(TimeStamp(13_001)).phase, Phase::Ramping);
+
+        runner.update_measurement(&sensor, 64.0, TimeStamp(14_000));
+        runner.tick(TimeStamp(14_000));
+        assert_eq!(runner.progress(TimeStamp(14_000)).phase, Phase::Holding);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config: ProgramConfig = serde_json::from_str(STEP_MASH).unwrap();
+        config.steps[0]
+            .targets
+            .insert(ClientId::from("boil"), 100.0);
+        assert!(config.validate().is_err());
+    }
+}
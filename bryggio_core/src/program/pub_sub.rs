@@ -0,0 +1,251 @@
+use crate::control::pub_sub::ControllerSubMsg;
+use crate::logger::{error, info};
+use crate::program::{ProgramConfig, ProgramError, ProgramProgress, ProgramRunner, Targets};
+use crate::pub_sub::{
+    heartbeat::Heartbeat, nats_client::NatsClient, nats_client::NatsClientConfig, ClientId,
+    ClientState, MessageParseError, PubSubClient, PubSubError, PubSubMsg, Subject,
+};
+use crate::sensor::SensorMsg;
+use crate::supervisor::pub_sub::SupervisorPubMsg;
+use crate::time::{TimeStamp, LOOP_PAUSE_TIME};
+use nats::{Message, Subscription};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::thread::sleep;
+
+/// Pub-sub client running a brew program
+pub struct ProgramClient {
+    id: ClientId,
+    runner: ProgramRunner,
+    client: NatsClient,
+}
+
+impl PubSubClient for ProgramClient {
+    fn client_loop(mut self) -> Result<(), PubSubError> {
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: self.id.clone(),
+            }
+            .subject(),
+        )?;
+        // Progress and heartbeats are published under the same prefix, they are ignored when
+        // parsing commands.
+        let commands = self.subscribe(&Subject(format!("program.{}.*", self.id)))?;
+        let sensor_ids: Vec<ClientId> = self
+            .runner
+            .config()
+            .controllers
+            .iter()
+            .map(|contr| contr.sensor_id.clone())
+            .collect();
+        let sensors = sensor_ids
+            .iter()
+            .map(|id| self.subscribe(&SensorMsg::subject(id)))
+            .collect::<Result<Vec<_>, _>>()?;
+        log_info(&self, &format!("Starting program '{}'", self.id));
+
+        let targets = self.runner.start(TimeStamp::now());
+        self.set_targets(&targets);
+        self.publish_progress();
+        let mut heartbeat = Heartbeat::new("program", &self.id);
+        let mut last_progress = TimeStamp::now();
+        loop {
+            heartbeat.beat(&self, ClientState::Active);
+            if let Some(msg) = kill_cmd.try_next() {
+                msg.respond(serde_json::to_string(&()).expect("Can always serialize"))
+                    .map_err(|err| PubSubError::Reply {
+                        task: "kill program",
+                        msg: msg.clone(),
+                        source: err,
+                    })?;
+                log_info(&self, "Program client killed");
+                return Ok(());
+            }
+
+            for msg in commands.try_iter() {
+                if let Ok(cmd) = ProgramSubMsg::try_from(&msg) {
+                    let response = match self.process_command(cmd) {
+                        Ok(()) => {
+                            heartbeat.clear_error();
+                            format!("Program '{}': {:?}", self.id, cmd)
+                        }
+                        Err(err) => {
+                            heartbeat.set_error(&err);
+                            err.to_string()
+                        }
+                    };
+                    if msg.reply.is_some() {
+                        msg.respond(response).map_err(|err| PubSubError::Reply {
+                            task: "program command",
+                            msg: msg.clone(),
+                            source: err,
+                        })?;
+                    }
+                    self.publish_progress();
+                }
+            }
+
+            let now = TimeStamp::now();
+            for (sensor_id, sub) in sensor_ids.iter().zip(sensors.iter()) {
+                if let Some(msg) = sub.try_iter().last() {
+                    if let Ok(SensorMsg { meas: Ok(meas), .. }) = SensorMsg::try_from(msg) {
+                        self.runner.update_measurement(sensor_id, meas, now);
+                    }
+                }
+            }
+
+            if let Some(targets) = self.runner.tick(now) {
+                self.set_targets(&targets);
+                self.publish_progress();
+            }
+            if now >= last_progress + PROGRESS_PERIOD {
+                self.publish_progress();
+                last_progress = now;
+            }
+            if self.runner.is_done() {
+                self.publish_progress();
+                log_info(&self, &format!("Program '{}' done", self.id));
+                return Ok(());
+            }
+            sleep(LOOP_PAUSE_TIME);
+        }
+    }
+
+    fn subscribe(&self, subject: &Subject) -> Result<Subscription, PubSubError> {
+        self.client.subscribe(subject)
+    }
+
+    fn publish(&self, subject: &Subject, msg: &PubSubMsg) -> Result<(), PubSubError> {
+        self.client.publish(subject, msg)
+    }
+}
+
+/// Time between periodic progress updates.
+const PROGRESS_PERIOD: TimeStamp = TimeStamp(1000);
+
+impl ProgramClient {
+    pub fn try_new(
+        config: ProgramConfig,
+        nats_config: &NatsClientConfig,
+    ) -> Result<Self, PubSubError> {
+        let id = config.program_id.clone();
+        let runner = ProgramRunner::try_new(config)
+            .map_err(|err| PubSubError::Configuration(err.to_string()))?;
+        let client = NatsClient::try_new(nats_config)?;
+        Ok(ProgramClient { id, runner, client })
+    }
+
+    fn process_command(&mut self, cmd: ProgramSubMsg) -> Result<(), ProgramError> {
+        match cmd {
+            ProgramSubMsg::Pause => self.runner.pause(),
+            ProgramSubMsg::Resume => self.runner.resume(TimeStamp::now()),
+            ProgramSubMsg::Skip => {
+                if let Some(targets) = self.runner.skip(TimeStamp::now())? {
+                    self.set_targets(&targets);
+                }
+                Ok(())
+            }
+            ProgramSubMsg::Abort => self.runner.abort(),
+        }
+    }
+
+    fn set_targets(&self, targets: &Targets) {
+        for (contr_id, target) in targets {
+            log_info(
+                self,
+                &format!("Setting target {} for controller '{}'", target, contr_id),
+            );
+            let msg = ControllerSubMsg::SetTarget(*target);
+            if let Err(err) = self.publish(&ControllerSubMsg::subject(contr_id), &msg.into()) {
+                log_error(
+                    self,
+                    &format!(
+                        "Failed setting target for controller '{}': {}",
+                        contr_id, err
+                    ),
+                );
+            }
+        }
+    }
+
+    fn publish_progress(&self) {
+        let progress = self.runner.progress(TimeStamp::now());
+        if let Err(err) = self.publish(&ProgramProgress::subject(&self.id), &progress.into()) {
+            log_error(self, &format!("Could not publish progress: {}", err));
+        }
+    }
+}
+
+impl ProgramProgress {
+    pub fn subject(id: &ClientId) -> Subject {
+        Subject(format!("program.{}.progress", id))
+    }
+}
+
+impl From<ProgramProgress> for PubSubMsg {
+    fn from(msg: ProgramProgress) -> PubSubMsg {
+        PubSubMsg(serde_json::to_string(&msg).expect("Pub sub serialization error"))
+    }
+}
+
+fn log_info(client: &ProgramClient, msg: &str) {
+    info(
+        client,
+        String::from(msg),
+        &format!("program.{}", &client.id),
+    );
+}
+
+fn log_error(client: &ProgramClient, msg: &str) {
+    error(
+        client,
+        String::from(msg),
+        &format!("program.{}", &client.id),
+    );
+}
+
+/// Commands to a running program, sent on `program.<id>.<command>`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ProgramSubMsg {
+    #[serde(rename = "pause")]
+    Pause,
+    #[serde(rename = "resume")]
+    Resume,
+    #[serde(rename = "skip")]
+    Skip,
+    #[serde(rename = "abort")]
+    Abort,
+}
+
+impl ProgramSubMsg {
+    pub fn subject(&self, id: &ClientId) -> Subject {
+        let cmd = match self {
+            ProgramSubMsg::Pause => "pause",
+            ProgramSubMsg::Resume => "resume",
+            ProgramSubMsg::Skip => "skip",
+            ProgramSubMsg::Abort => "abort",
+        };
+        Subject(format!("program.{}.{}", id, cmd))
+    }
+}
+
+impl TryFrom<&Message> for ProgramSubMsg {
+    type Error = MessageParseError;
+    fn try_from(msg: &Message) -> Result<Self, Self::Error> {
+        match msg.subject.rsplit('.').next() {
+            Some("pause") => Ok(ProgramSubMsg::Pause),
+            Some("resume") => Ok(ProgramSubMsg::Resume),
+            Some("skip") => Ok(ProgramSubMsg::Skip),
+            Some("abort") => Ok(ProgramSubMsg::Abort),
+            _ => Err(MessageParseError::InvalidSubject(Subject(
+                msg.subject.clone(),
+            ))),
+        }
+    }
+}
+
+impl From<ProgramSubMsg> for PubSubMsg {
+    fn from(_msg: ProgramSubMsg) -> PubSubMsg {
+        PubSubMsg::empty()
+    }
+}
@@ -0,0 +1,127 @@
+//! Client heartbeats
+//!
+//! Every client periodically publishes a [`HeartbeatMsg`] on `<kind>.<id>.heartbeat`,
+//! so that the supervisor can tell a dead client from a quiet one.
+use crate::logger::error;
+use crate::pub_sub::{ClientId, ClientState, PubSubClient, PubSubMsg, Subject};
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+
+/// Time between two heartbeats from the same client.
+pub const HEARTBEAT_PERIOD: TimeStamp = TimeStamp(1000);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HeartbeatMsg {
+    pub id: ClientId,
+    pub timestamp: TimeStamp,
+    pub state: ClientState,
+    pub uptime_ms: u128,
+    pub last_error: Option<String>,
+}
+
+impl HeartbeatMsg {
+    /// Subject on which heartbeats from all clients are published
+    pub fn wildcard_subject() -> Subject {
+        Subject::from("*.*.heartbeat")
+    }
+}
+
+impl From<HeartbeatMsg> for PubSubMsg {
+    fn from(msg: HeartbeatMsg) -> PubSubMsg {
+        PubSubMsg(serde_json::to_string(&msg).expect("Can always serialize"))
+    }
+}
+
+/// Heartbeat state kept by each client.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    id: ClientId,
+    subject: Subject,
+    /// Sub-subject of logged heartbeat errors, `<kind>.<id>`.
+    log_subject: String,
+    started: TimeStamp,
+    last_beat: Option<TimeStamp>,
+    last_error: Option<String>,
+}
+
+impl Heartbeat {
+    /// `kind` is the first part of the subject, e.g. `sensor` or `actor`.
+    pub fn new(kind: &str, id: &ClientId) -> Self {
+        Heartbeat {
+            id: id.clone(),
+            subject: Subject(format!("{}.{}.heartbeat", kind, id)),
+            log_subject: format!("{}.{}", kind, id),
+            started: TimeStamp::now(),
+            last_beat: None,
+            last_error: None,
+        }
+    }
+
+    /// Remember an error, it is included in all following heartbeats until cleared.
+    pub fn set_error<E: ToString>(&mut self, err: &E) {
+        self.last_error = Some(err.to_string());
+    }
+
+    /// Forget the last error, once the client has recovered from it.
+    pub fn clear_error(&mut self) {
+        self.last_error = None;
+    }
+
+    /// Publish a heartbeat if [`HEARTBEAT_PERIOD`] has passed since the last one.
+    ///
+    /// Meant to be called on every iteration of a client loop.
+    /// Failing to publish is not treated as an error; a missing heartbeat is what the supervisor
+    /// is looking for.
+    pub fn beat<C: PubSubClient>(&mut self, client: &C, state: ClientState) {
+        let now = TimeStamp::now();
+        if !self.is_due(now) {
+            return;
+        }
+        self.last_beat = Some(now);
+        let msg = HeartbeatMsg {
+            id: self.id.clone(),
+            timestamp: now,
+            state,
+            uptime_ms: (now - self.started).0,
+            last_error: self.last_error.clone(),
+        };
+        if let Err(err) = client.publish(&self.subject, &msg.into()) {
+            error(
+                client,
+                format!("Failed publishing heartbeat: {}", err),
+                &self.log_subject,
+            );
+        }
+    }
+
+    fn is_due(&self, now: TimeStamp) -> bool {
+        match self.last_beat {
+            Some(last_beat) => now >= last_beat + HEARTBEAT_PERIOD,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat_due() {
+        let mut heartbeat = Heartbeat::new("sensor", &ClientId::from("mash"));
+        assert_eq!(heartbeat.subject, Subject::from("sensor.mash.heartbeat"));
+        assert!(heartbeat.is_due(TimeStamp(0)));
+        heartbeat.last_beat = Some(TimeStamp(5000));
+        assert!(!heartbeat.is_due(TimeStamp(5999)));
+        assert!(heartbeat.is_due(TimeStamp(6000)));
+    }
+
+    #[test]
+    fn test_heartbeat_error() {
+        let mut heartbeat = Heartbeat::new("actor", &ClientId::from("kettle"));
+        heartbeat.set_error(&"relay stuck");
+        assert_eq!(heartbeat.last_error.as_deref(), Some("relay stuck"));
+        heartbeat.clear_error();
+        assert_eq!(heartbeat.last_error, None);
+    }
+}
@@ -1,12 +1,14 @@
 use std::str::Utf8Error;
 
 use derive_more::{Display, From};
+pub mod heartbeat;
 pub mod nats_client;
 use nats::{Message, Subscription};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ClientState {
     Inactive,
     Active,
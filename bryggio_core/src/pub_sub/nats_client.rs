@@ -9,6 +9,8 @@ use crate::pub_sub::{MessageParseError, PubSubError, PubSubMsg, Subject};
 use nats::{Connection, Options, Subscription};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::fs::write;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
 use std::process::{Child, Command};
 use std::str::from_utf8;
 use std::thread::sleep;
@@ -46,7 +48,13 @@ pub fn run_nats_server(config: &NatsServerConfig, bin_path: &Path) -> Result<Chi
         ))
     })?;
 
-    let child = Command::new(bin_path).arg("-c").arg(config_name).spawn();
+    let mut command = Command::new(bin_path);
+    command.arg("-c").arg(config_name);
+    // Run the server in its own process group, so that a Ctrl-C in the terminal only reaches
+    // the supervisor, which needs the server to shut down its clients in an orderly fashion.
+    #[cfg(unix)]
+    command.process_group(0);
+    let child = command.spawn();
     // Sleeps for a short while to ensure that the server is up and running before
     // the first connection comes.
     sleep(Duration::from_millis(10));
@@ -98,6 +106,17 @@ impl NatsClient {
             .request(&subject.0, &msg.0)
             .map_err(|err| PubSubError::Publish(err.to_string()))
     }
+
+    pub fn request_timeout(
+        &self,
+        subject: &Subject,
+        msg: &PubSubMsg,
+        timeout: Duration,
+    ) -> Result<nats::Message, PubSubError> {
+        self.0
+            .request_timeout(&subject.0, &msg.0, timeout)
+            .map_err(|err| PubSubError::Publish(err.to_string()))
+    }
 }
 
 // TODO: typedefs, e.g. Port
@@ -0,0 +1,271 @@
+//! Safety interlock
+//!
+//! Guards map actors to the sensors watching over them, e.g. a heating element and the
+//! temperature sensor in its kettle.
+//! A guard trips when its sensor reads above the maximum temperature, or when no valid
+//! measurement has arrived for too long, e.g. because the sensor thread died.
+//! A tripped actor is turned off and latched by [`SafetyClient`]; it ignores new signals until it
+//! is reset on `safety.reset`, which is only accepted once none of its guards are violated.
+//!
+//! Guards are read when the supervisor starts, reloading the config does not change them.
+//! Latches are written to the latch file, if one is configured, so that they survive a restart of
+//! the safety client or the supervisor.
+use crate::pub_sub::ClientId;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub mod pub_sub;
+pub use pub_sub::SafetyClient;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SafetyConfig {
+    #[serde(default)]
+    pub guards: Vec<Guard>,
+    /// File to keep the latched actors in, latches are lost on restart if missing.
+    #[serde(default)]
+    pub latch_file: Option<PathBuf>,
+}
+
+/// Limits on a sensor guarding an actor, an actor can have several guards.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Guard {
+    pub actor_id: ClientId,
+    pub sensor_id: ClientId,
+    #[serde(default)]
+    pub max_temp: Option<f32>,
+    /// Maximum time between two valid measurements.
+    #[serde(default)]
+    pub max_staleness_ms: Option<u64>,
+}
+
+impl Guard {
+    fn violation(
+        &self,
+        last_meas: Option<&(TimeStamp, f32)>,
+        since: TimeStamp,
+        now: TimeStamp,
+    ) -> Option<String> {
+        if let (Some(max_temp), Some((_, meas))) = (self.max_temp, last_meas) {
+            if *meas > max_temp {
+                return Some(format!(
+                    "sensor '{}' reads {}, above the limit {}",
+                    self.sensor_id, meas, max_temp
+                ));
+            }
+        }
+        if let Some(max_staleness_ms) = self.max_staleness_ms {
+            let last = last_meas.map_or(since, |(timestamp, _)| *timestamp);
+            let staleness = now.0.saturating_sub(last.0);
+            if staleness > u128::from(max_staleness_ms) {
+                return Some(format!(
+                    "no valid measurement from sensor '{}' in {} ms",
+                    self.sensor_id, staleness
+                ));
+            }
+        }
+        None
+    }
+}
+
+/// Interlock state, checks the guards and keeps track of latched actors.
+#[derive(Debug, Clone)]
+pub struct Interlock {
+    guards: Vec<Guard>,
+    /// Time of the latest valid measurement from each sensor, and its value.
+    measurements: HashMap<ClientId, (TimeStamp, f32)>,
+    /// Latched actors with the reason they tripped.
+    latched: HashMap<ClientId, String>,
+    /// Staleness is counted from here for sensors that have not sent anything yet.
+    started: TimeStamp,
+}
+
+impl Interlock {
+    pub fn new(config: SafetyConfig, now: TimeStamp) -> Self {
+        Interlock {
+            guards: config.guards,
+            measurements: HashMap::new(),
+            latched: HashMap::new(),
+            started: now,
+        }
+    }
+
+    /// Sensors used by any guard, without duplicates.
+    pub fn sensor_ids(&self) -> Vec<ClientId> {
+        let mut ids: Vec<ClientId> = Vec::new();
+        for guard in &self.guards {
+            if !ids.contains(&guard.sensor_id) {
+                ids.push(guard.sensor_id.clone());
+            }
+        }
+        ids
+    }
+
+    pub fn latched(&self) -> &HashMap<ClientId, String> {
+        &self.latched
+    }
+
+    /// Latch actors again after a restart, see [`load_latches`].
+    pub fn restore(&mut self, latched: HashMap<ClientId, String>) {
+        self.latched.extend(latched);
+    }
+
+    /// Record a valid measurement, `now` is the time it was received.
+    ///
+    /// The receive time is used rather than the measurement timestamp, since remote nodes may
+    /// not have their clocks in sync with the supervisor.
+    pub fn update_measurement(&mut self, sensor_id: &ClientId, meas: f32, now: TimeStamp) {
+        self.measurements.insert(sensor_id.clone(), (now, meas));
+    }
+
+    /// Check all guards, returns the actors that tripped since the last check.
+    pub fn check(&mut self, now: TimeStamp) -> Vec<(ClientId, String)> {
+        let mut tripped = Vec::new();
+        for guard in &self.guards {
+            if self.latched.contains_key(&guard.actor_id) {
+                continue;
+            }
+            if let Some(reason) =
+                guard.violation(self.measurements.get(&guard.sensor_id), self.started, now)
+            {
+                self.latched.insert(guard.actor_id.clone(), reason.clone());
+                tripped.push((guard.actor_id.clone(), reason));
+            }
+        }
+        tripped
+    }
+
+    /// Release a latched actor, refused while any of its guards are still violated.
+    pub fn reset(&mut self, actor_id: &ClientId, now: TimeStamp) -> Result<(), SafetyError> {
+        self.check_reset(actor_id, now)?;
+        self.latched.remove(actor_id);
+        Ok(())
+    }
+
+    /// Check that a latched actor may be reset, without releasing it.
+    pub fn check_reset(&self, actor_id: &ClientId, now: TimeStamp) -> Result<(), SafetyError> {
+        if !self.latched.contains_key(actor_id) {
+            return Err(SafetyError::NotLatched(actor_id.clone()));
+        }
+        if let Some(reason) = self
+            .guards
+            .iter()
+            .filter(|guard| &guard.actor_id == actor_id)
+            .find_map(|guard| {
+                guard.violation(self.measurements.get(&guard.sensor_id), self.started, now)
+            })
+        {
+            return Err(SafetyError::Violated(actor_id.clone(), reason));
+        }
+        Ok(())
+    }
+}
+
+/// Read the latched actors, no file means that nothing is latched.
+pub fn load_latches(path: &Path) -> Result<HashMap<ClientId, String>, SafetyError> {
+    let latched = match fs::read_to_string(path) {
+        Ok(latched) => latched,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(SafetyError::LatchFile(err.to_string())),
+    };
+    serde_json::from_str(&latched).map_err(|err| SafetyError::LatchFile(err.to_string()))
+}
+
+pub fn save_latches(path: &Path, latched: &HashMap<ClientId, String>) -> Result<(), SafetyError> {
+    let latched = serde_json::to_string_pretty(latched)
+        .map_err(|err| SafetyError::LatchFile(err.to_string()))?;
+    // Write to a temporary file first, so that a crash mid-write never loses the latches.
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, latched)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|err| SafetyError::LatchFile(err.to_string()))
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SafetyError {
+    #[error("Actor '{0}' is not latched")]
+    NotLatched(ClientId),
+    #[error("Actor '{0}' cannot be reset, {1}")]
+    Violated(ClientId, String),
+    #[error("Error accessing latch file: {0}")]
+    LatchFile(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kettle_guard() -> Interlock {
+        let config = SafetyConfig {
+            guards: vec![Guard {
+                actor_id: ClientId::from("heater"),
+                sensor_id: ClientId::from("kettle_temp"),
+                max_temp: Some(105.0),
+                max_staleness_ms: Some(5000),
+            }],
+            latch_file: None,
+        };
+        Interlock::new(config, TimeStamp(0))
+    }
+
+    #[test]
+    fn test_max_temp_latches_until_reset() {
+        let mut interlock = kettle_guard();
+        let heater = ClientId::from("heater");
+        let sensor = ClientId::from("kettle_temp");
+        interlock.update_measurement(&sensor, 99.0, TimeStamp(1000));
+        assert!(interlock.check(TimeStamp(1000)).is_empty());
+
+        interlock.update_measurement(&sensor, 110.0, TimeStamp(2000));
+        let tripped = interlock.check(TimeStamp(2000));
+        assert_eq!(tripped.len(), 1);
+        assert_eq!(tripped[0].0, heater);
+        // Only reported once.
+        assert!(interlock.check(TimeStamp(2100)).is_empty());
+        assert!(interlock.reset(&heater, TimeStamp(2100)).is_err());
+
+        interlock.update_measurement(&sensor, 100.0, TimeStamp(3000));
+        assert!(interlock.check(TimeStamp(3000)).is_empty());
+        assert!(interlock.latched().contains_key(&heater));
+        assert_eq!(interlock.check_reset(&heater, TimeStamp(3000)), Ok(()));
+        assert!(interlock.latched().contains_key(&heater));
+        assert_eq!(interlock.reset(&heater, TimeStamp(3000)), Ok(()));
+        assert!(interlock.latched().is_empty());
+    }
+
+    #[test]
+    fn test_staleness() {
+        let mut interlock = kettle_guard();
+        let sensor = ClientId::from("kettle_temp");
+        assert!(interlock.check(TimeStamp(5000)).is_empty());
+        assert_eq!(interlock.check(TimeStamp(5001)).len(), 1);
+
+        let mut interlock = kettle_guard();
+        interlock.update_measurement(&sensor, 60.0, TimeStamp(4000));
+        assert!(interlock.check(TimeStamp(9000)).is_empty());
+        assert_eq!(interlock.check(TimeStamp(9001)).len(), 1);
+    }
+
+    #[test]
+    fn test_latches_survive_restart() {
+        let path =
+            std::env::temp_dir().join(format!("bryggio_latches_{}.json", std::process::id()));
+        let heater = ClientId::from("heater");
+        assert!(load_latches(&path).unwrap().is_empty());
+
+        let mut interlock = kettle_guard();
+        interlock.update_measurement(&ClientId::from("kettle_temp"), 110.0, TimeStamp(1000));
+        assert_eq!(interlock.check(TimeStamp(1000)).len(), 1);
+        save_latches(&path, interlock.latched()).unwrap();
+
+        let mut restarted = kettle_guard();
+        restarted.restore(load_latches(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(restarted.latched().contains_key(&heater));
+        // Still latched, it is not reported as tripped again.
+        assert!(restarted.check(TimeStamp(2000)).is_empty());
+    }
+}
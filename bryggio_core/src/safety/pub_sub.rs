@@ -0,0 +1,225 @@
+use crate::actor::pub_sub::{actor_latch_subject, actor_reset_latch_subject};
+use crate::logger::{error, info};
+use crate::pub_sub::{
+    heartbeat::Heartbeat, nats_client::decode_nats_data, nats_client::NatsClient,
+    nats_client::NatsClientConfig, ClientId, ClientState, PubSubClient, PubSubError, PubSubMsg,
+    Subject,
+};
+use crate::safety::{load_latches, save_latches, Interlock, SafetyConfig, SafetyError};
+use crate::sensor::SensorMsg;
+use crate::supervisor::pub_sub::SupervisorPubMsg;
+use crate::time::{TimeStamp, LOOP_PAUSE_TIME};
+use nats::{Message, Subscription};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::thread::sleep;
+
+/// Time between re-sending the latch to latched actors.
+///
+/// An actor client that is restarted by the supervisor comes back unlatched,
+/// re-sending the latch makes sure it is turned off again.
+const LATCH_REFRESH_PERIOD: TimeStamp = TimeStamp(1000);
+
+/// Pub-sub client enforcing the safety guards
+pub struct SafetyClient {
+    id: ClientId,
+    interlock: Interlock,
+    latch_file: Option<PathBuf>,
+    client: NatsClient,
+}
+
+impl PubSubClient for SafetyClient {
+    fn client_loop(mut self) -> Result<(), PubSubError> {
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: self.id.clone(),
+            }
+            .subject(),
+        )?;
+        let reset_cmd = self.subscribe(&SafetyClient::reset_subject())?;
+        let sensor_ids = self.interlock.sensor_ids();
+        let sensors = sensor_ids
+            .iter()
+            .map(|id| self.subscribe(&SensorMsg::subject(id)))
+            .collect::<Result<Vec<_>, _>>()?;
+        info(
+            &self,
+            format!("Starting safety client guarding sensors {:?}", sensor_ids),
+            "safety",
+        );
+
+        // Restored latches are enforced right away, not only at the first refresh.
+        for (actor_id, reason) in self.interlock.latched().clone() {
+            self.latch(&actor_id, &reason);
+        }
+
+        let mut heartbeat = Heartbeat::new("safety", &self.id);
+        let mut last_refresh = TimeStamp::now();
+        loop {
+            heartbeat.beat(&self, ClientState::Active);
+            if let Some(msg) = kill_cmd.try_next() {
+                msg.respond(serde_json::to_string(&()).expect("Can always serialize"))
+                    .map_err(|err| PubSubError::Reply {
+                        task: "kill safety",
+                        msg: msg.clone(),
+                        source: err,
+                    })?;
+                info(&self, String::from("Safety client killed"), "safety");
+                return Ok(());
+            }
+
+            for (sensor_id, sub) in sensor_ids.iter().zip(sensors.iter()) {
+                for msg in sub.try_iter() {
+                    // Errors do not count as measurements, a failing sensor trips on staleness.
+                    if let Ok(SensorMsg { meas: Ok(meas), .. }) = SensorMsg::try_from(msg) {
+                        self.interlock
+                            .update_measurement(sensor_id, meas, TimeStamp::now());
+                    }
+                }
+            }
+
+            let now = TimeStamp::now();
+            let tripped = self.interlock.check(now);
+            if !tripped.is_empty() {
+                self.save_latches();
+            }
+            for (actor_id, reason) in tripped {
+                let err_msg = format!(
+                    "Safety interlock tripped for actor '{}': {}",
+                    actor_id, reason
+                );
+                heartbeat.set_error(&err_msg);
+                error(&self, err_msg, "safety");
+                self.latch(&actor_id, &reason);
+            }
+            if now >= last_refresh + LATCH_REFRESH_PERIOD {
+                for (actor_id, reason) in self.interlock.latched().clone() {
+                    self.latch(&actor_id, &reason);
+                }
+                last_refresh = now;
+            }
+
+            for msg in reset_cmd.try_iter() {
+                self.reset(&msg)?;
+            }
+            if self.interlock.latched().is_empty() {
+                heartbeat.clear_error();
+            }
+            sleep(LOOP_PAUSE_TIME);
+        }
+    }
+
+    fn subscribe(&self, subject: &Subject) -> Result<Subscription, PubSubError> {
+        self.client.subscribe(subject)
+    }
+
+    fn publish(&self, subject: &Subject, msg: &PubSubMsg) -> Result<(), PubSubError> {
+        self.client.publish(subject, msg)
+    }
+}
+
+impl SafetyClient {
+    pub fn try_new(
+        id: ClientId,
+        config: SafetyConfig,
+        nats_config: &NatsClientConfig,
+    ) -> Result<Self, PubSubError> {
+        let client = NatsClient::try_new(nats_config)?;
+        Ok(SafetyClient {
+            id,
+            latch_file: config.latch_file.clone(),
+            interlock: Interlock::new(config, TimeStamp::now()),
+            client,
+        })
+    }
+
+    /// Latch the actors stored in the latch file again, before the client is started.
+    pub fn restore_latches(&mut self) -> Result<(), SafetyError> {
+        if let Some(path) = &self.latch_file {
+            let latched = load_latches(path)?;
+            if !latched.is_empty() {
+                info(
+                    self,
+                    format!("Restored latched actors {:?}", latched.keys()),
+                    "safety",
+                );
+            }
+            self.interlock.restore(latched);
+        }
+        Ok(())
+    }
+
+    /// Failing to save is logged, the latches are still enforced while the client runs.
+    fn save_latches(&self) {
+        if let Some(path) = &self.latch_file {
+            if let Err(err) = save_latches(path, self.interlock.latched()) {
+                error(
+                    self,
+                    format!("Failed saving latches to '{}': {}", path.display(), err),
+                    "safety",
+                );
+            }
+        }
+    }
+
+    /// Subject for resetting a latched actor, the message data is the actor id.
+    pub fn reset_subject() -> Subject {
+        Subject::from("safety.reset")
+    }
+
+    /// Turn off and latch an actor.
+    ///
+    /// The latch is published rather than requested, a missing actor must not stall the guards
+    /// of other actors.
+    fn latch(&self, actor_id: &ClientId, reason: &str) {
+        let msg = PubSubMsg(serde_json::to_string(reason).expect("Can always serialize"));
+        if let Err(err) = self.publish(&actor_latch_subject(actor_id), &msg) {
+            error(
+                self,
+                format!("Failed latching actor '{}': {}", actor_id, err),
+                "safety",
+            );
+        }
+    }
+
+    /// Reset a latched actor, replies with a serialised `Result<(), String>`.
+    ///
+    /// The actor is only released by the interlock once the reset has been published,
+    /// so that an actor which never got the reset is still treated as latched.
+    fn reset(&mut self, msg: &Message) -> Result<(), PubSubError> {
+        let report = decode_nats_data::<ClientId>(&msg.data)
+            .map_err(|err| err.to_string())
+            .and_then(|actor_id| {
+                let now = TimeStamp::now();
+                self.interlock
+                    .check_reset(&actor_id, now)
+                    .map_err(|err| err.to_string())?;
+                self.publish(&actor_reset_latch_subject(&actor_id), &PubSubMsg::empty())
+                    .map_err(|err| err.to_string())?;
+                self.interlock
+                    .reset(&actor_id, now)
+                    .map_err(|err| err.to_string())?;
+                Ok(actor_id)
+            });
+        let report = match report {
+            Ok(actor_id) => {
+                self.save_latches();
+                info(self, format!("Actor '{}' reset", actor_id), "safety");
+                Ok(())
+            }
+            Err(err) => {
+                error(self, format!("Reset failed: {}", err), "safety");
+                Err(err)
+            }
+        };
+        if msg.reply.is_some() {
+            msg.respond(serde_json::to_string(&report).expect("Can always serialize"))
+                .map_err(|err| PubSubError::Reply {
+                    task: "reset actor",
+                    msg: msg.clone(),
+                    source: err,
+                })?;
+        }
+        Ok(())
+    }
+}
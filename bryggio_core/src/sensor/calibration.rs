@@ -0,0 +1,184 @@
+//! Calibration of sensor measurements
+//!
+//! The calibration of a sensor maps the measured value to the reference value,
+//! it is applied by the [`SensorClient`](super::SensorClient) before filtering and publishing.
+//!
+//! Reference points can be captured live with [`SensorCommand`](super::SensorCommand)s,
+//! [`Calibration::fit`] then picks the calibration from the number of points:
+//! an offset for one, a two-point correction for two and a quadratic polynomial for more.
+use crate::sensor::SensorError;
+use serde::{Deserialize, Serialize};
+
+/// A measured value and the value of a reference thermometer at the same time.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ReferencePoint {
+    pub measured: f32,
+    pub reference: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Calibration {
+    /// Added to the measured value.
+    #[serde(rename = "offset")]
+    Offset(f32),
+    /// Linear correction through two points, e.g., ice water and boiling water.
+    #[serde(rename = "two_point")]
+    TwoPoint {
+        low: ReferencePoint,
+        high: ReferencePoint,
+    },
+    /// Coefficients in ascending order, `c0 + c1 * x + c2 * x^2 + ...`.
+    #[serde(rename = "polynomial")]
+    Polynomial(Vec<f32>),
+}
+
+impl Calibration {
+    pub fn validate(&self) -> Result<(), SensorError> {
+        let valid = match self {
+            Calibration::Offset(offset) => offset.is_finite(),
+            Calibration::TwoPoint { low, high } => {
+                [low.measured, low.reference, high.measured, high.reference]
+                    .iter()
+                    .all(|value| value.is_finite())
+                    && low.measured != high.measured
+            }
+            Calibration::Polynomial(coeffs) => {
+                !coeffs.is_empty() && coeffs.iter().all(|coeff| coeff.is_finite())
+            }
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(SensorError::InvalidParam(format!(
+                "Invalid calibration {:?}",
+                self
+            ))),
+        }
+    }
+
+    pub fn apply(&self, measured: f32) -> f32 {
+        match self {
+            Calibration::Offset(offset) => measured + offset,
+            Calibration::TwoPoint { low, high } => {
+                let slope = (high.reference - low.reference) / (high.measured - low.measured);
+                low.reference + slope * (measured - low.measured)
+            }
+            Calibration::Polynomial(coeffs) => coeffs
+                .iter()
+                .rev()
+                .fold(0.0, |value, coeff| value * measured + coeff),
+        }
+    }
+
+    /// Calibration through the reference points, a least squares fit for more than two points.
+    pub fn fit(points: &[ReferencePoint]) -> Result<Calibration, SensorError> {
+        let calibration = match points {
+            [] => {
+                return Err(SensorError::Calibration(String::from(
+                    "No reference points captured",
+                )))
+            }
+            [point] => Calibration::Offset(point.reference - point.measured),
+            [first, second] if first.measured <= second.measured => Calibration::TwoPoint {
+                low: *first,
+                high: *second,
+            },
+            [first, second] => Calibration::TwoPoint {
+                low: *second,
+                high: *first,
+            },
+            _ => Calibration::Polynomial(fit_quadratic(points)?),
+        };
+        calibration.validate().map_err(|_| {
+            SensorError::Calibration(format!(
+                "The reference points {:?} need distinct measurements",
+                points
+            ))
+        })?;
+        Ok(calibration)
+    }
+}
+
+/// Solve the normal equations of the least squares fit of `c0 + c1 * x + c2 * x^2`.
+fn fit_quadratic(points: &[ReferencePoint]) -> Result<Vec<f32>, SensorError> {
+    const N: usize = 3;
+    let mut system = [[0.0f64; N + 1]; N];
+    for point in points {
+        let (x, y) = (f64::from(point.measured), f64::from(point.reference));
+        for (row, equation) in system.iter_mut().enumerate() {
+            for (col, value) in equation.iter_mut().take(N).enumerate() {
+                *value += x.powi((row + col) as i32);
+            }
+            equation[N] += y * x.powi(row as i32);
+        }
+    }
+    // Gaussian elimination with partial pivoting.
+    for col in 0..N {
+        let pivot = (col..N)
+            .max_by(|a, b| system[*a][col].abs().total_cmp(&system[*b][col].abs()))
+            .expect("Range is not empty");
+        if system[pivot][col].abs() < 1e-9 {
+            return Err(SensorError::Calibration(String::from(
+                "A quadratic fit needs at least three distinct measurements",
+            )));
+        }
+        system.swap(col, pivot);
+        let (upper, lower) = system.split_at_mut(col + 1);
+        let pivot_equation = &upper[col];
+        for equation in lower.iter_mut() {
+            let factor = equation[col] / pivot_equation[col];
+            for (value, pivot_value) in equation.iter_mut().zip(pivot_equation).skip(col) {
+                *value -= factor * pivot_value;
+            }
+        }
+    }
+    let mut coeffs = [0.0f64; N];
+    for (row, equation) in system.iter().enumerate().rev() {
+        let known: f64 = (row + 1..N).map(|col| equation[col] * coeffs[col]).sum();
+        coeffs[row] = (equation[N] - known) / equation[row];
+    }
+    Ok(coeffs.iter().map(|coeff| *coeff as f32).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn point(measured: f32, reference: f32) -> ReferencePoint {
+        ReferencePoint {
+            measured,
+            reference,
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let calibration: Calibration = serde_json::from_str(
+            r#"{"two_point": {"low": {"measured": 0.5, "reference": 0.0},
+                              "high": {"measured": 99.5, "reference": 99.0}}}"#,
+        )
+        .unwrap();
+        calibration.validate().unwrap();
+        assert_approx_eq!(calibration.apply(50.0), 49.5);
+        let calibration = Calibration::Polynomial(vec![1.0, 2.0, 0.5]);
+        assert_approx_eq!(calibration.apply(2.0), 7.0);
+        assert!(Calibration::Polynomial(Vec::new()).validate().is_err());
+    }
+
+    #[test]
+    fn test_fit() {
+        let offset = Calibration::fit(&[point(20.8, 20.0)]).unwrap();
+        assert_approx_eq!(offset.apply(65.0), 64.2);
+        let two_point = Calibration::fit(&[point(99.5, 99.0), point(0.5, 0.0)]).unwrap();
+        assert_approx_eq!(two_point.apply(0.5), 0.0);
+        assert!(Calibration::fit(&[point(20.0, 20.0), point(20.0, 21.0)]).is_err());
+        // Points on 0.5 + 0.9 x + 0.001 x^2.
+        let points: Vec<ReferencePoint> = [0.0f32, 25.0, 50.0, 75.0, 100.0]
+            .iter()
+            .map(|x| point(*x, 0.5 + 0.9 * x + 0.001 * x * x))
+            .collect();
+        let polynomial = Calibration::fit(&points).unwrap();
+        assert_approx_eq!(polynomial.apply(60.0), 58.1, 1e-3);
+        assert!(Calibration::fit(&[point(1.0, 1.0), point(1.0, 1.0), point(2.0, 2.0)]).is_err());
+    }
+}
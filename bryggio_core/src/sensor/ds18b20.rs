@@ -2,6 +2,16 @@
 //!
 //! This driver is not embedded; it relies on the presence of a 1-wire compatible OS,
 //! and simply gets measurements by reading from a file descriptor provided by the OS.
+//! The OS registers each sensor in a directory named by its address under the 1-wire root,
+//! [`DS18B20_DIR`] by default, which is configured with
+//! [`Hardware::one_wire_dir`](crate::supervisor::config::Hardware::one_wire_dir).
+//!
+//! Reads are checked before they are trusted:
+//! - The `w1_slave` file holds the raw scratchpad, whose CRC is validated.
+//! - The value 85°C is what the sensor holds after a power-on reset, before its first conversion.
+//!   It is only accepted if it is consistent with the previous valid reading.
+//!
+//! A failed read is retried up to `max_retries` times, after which the last error is returned.
 // TODO: Clarify the relation between 1-wire (the protocol) and DS18B20 the actual sensor
 use crate::sensor::{Sensor, SensorError};
 use crate::utils;
@@ -9,16 +19,115 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::thread::sleep;
-use std::time::Duration;
+
+/// Temperature held by the sensor after a power-on reset.
+const POWER_ON_RESET_TEMP: f32 = 85.0;
+/// Largest difference to the previous reading for which 85°C is taken as a real temperature.
+const POWER_ON_RESET_TOLERANCE: f32 = 1.0;
+
+/// DS18B20 sensor config
+///
+/// Deserializes from the full config or from just the address, using the default settings.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Ds18b20Config {
+    pub address: Ds18b20Address,
+    #[serde(default)]
+    pub file: Ds18b20File,
+    /// Resolution in bits, 9 to 12, left as is if not set.
+    #[serde(default)]
+    pub resolution: Option<u8>,
+    /// Conversion time in ms, left as is if not set.
+    /// Requires a kernel with the `conv_time` attribute.
+    #[serde(default)]
+    pub conversion_time_ms: Option<u64>,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_max_retries() -> u32 {
+    2
+}
+
+impl Ds18b20Config {
+    /// Default settings
+    pub fn with_address(address: Ds18b20Address) -> Ds18b20Config {
+        Ds18b20Config {
+            address,
+            file: Ds18b20File::default(),
+            resolution: None,
+            conversion_time_ms: None,
+            max_retries: default_max_retries(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), SensorError> {
+        Ds18b20Address::verify(self.address.as_ref())?;
+        if let Some(resolution) = self.resolution {
+            if !(9..=12).contains(&resolution) {
+                return Err(SensorError::InvalidParam(format!(
+                    "Resolution must be 9 to 12 bits, got {}",
+                    resolution
+                )));
+            }
+        }
+        if self.conversion_time_ms == Some(0) {
+            return Err(SensorError::InvalidParam(String::from(
+                "Conversion time must be positive",
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Deserialize a [`Ds18b20Config`] from the full config or from just the address.
+pub(crate) fn deserialize_config<'de, D>(deserializer: D) -> Result<Ds18b20Config, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum AddressOrConfig {
+        Address(Ds18b20Address),
+        Config(Ds18b20Config),
+    }
+    Ok(match AddressOrConfig::deserialize(deserializer)? {
+        AddressOrConfig::Address(address) => Ds18b20Config::with_address(address),
+        AddressOrConfig::Config(config) => config,
+    })
+}
+
+/// File the temperature is read from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Ds18b20File {
+    /// Raw scratchpad with CRC, available on all kernels.
+    #[default]
+    #[serde(rename = "w1_slave")]
+    W1Slave,
+    /// Temperature only, without CRC.
+    #[serde(rename = "temperature")]
+    Temperature,
+}
+
+impl Ds18b20File {
+    fn file_name(&self) -> &'static str {
+        match self {
+            Ds18b20File::W1Slave => "w1_slave",
+            Ds18b20File::Temperature => "temperature",
+        }
+    }
+}
 
 /// DS18B20 temperature sensor
 #[derive(Debug)]
 pub struct Ds18b20 {
     /// User-specified ID, must be unique.
     pub id: String,
-    /// 1-wire address, fixed for each sensor
-    address: Ds18b20Address,
+    config: Ds18b20Config,
+    /// 1-wire root directory
+    one_wire_dir: PathBuf,
+    /// Whether the resolution and conversion time are written to the sensor.
+    settings_applied: bool,
+    last_valid: Option<f32>,
 }
 
 impl Ds18b20 {
@@ -26,16 +135,57 @@ impl Ds18b20 {
     ///
     /// NB: We do not check that the file descriptor actually exists,
     /// since it is better to log an error message than to fail the entire supervisor start-up.
-    pub fn try_new(id: &str, address: &str) -> Result<Ds18b20, SensorError> {
-        let address = Ds18b20Address::try_new(address)?;
+    /// For the same reason the settings are only written to the sensor on the first reading.
+    pub fn try_new(
+        id: &str,
+        config: &Ds18b20Config,
+        one_wire_dir: &Path,
+    ) -> Result<Ds18b20, SensorError> {
+        config.validate()?;
         let id = String::from(id);
-        Ok(Ds18b20 { id, address })
+        Ok(Ds18b20 {
+            id,
+            config: config.clone(),
+            one_wire_dir: one_wire_dir.to_path_buf(),
+            settings_applied: false,
+            last_valid: None,
+        })
+    }
+
+    fn device_dir(&self) -> PathBuf {
+        self.one_wire_dir.join(&self.config.address.0)
+    }
+
+    fn apply_settings(&self) -> Result<(), SensorError> {
+        let settings = [
+            ("resolution", self.config.resolution.map(u64::from)),
+            ("conv_time", self.config.conversion_time_ms),
+        ];
+        for (file, value) in settings {
+            if let Some(value) = value {
+                fs::write(self.device_dir().join(file), value.to_string()).map_err(|err| {
+                    SensorError::FileWrite(format!("Unable to set {} to {}: {}", file, value, err))
+                })?;
+            }
+        }
+        Ok(())
     }
 
-    fn device_path(&self) -> PathBuf {
-        Path::new(DS18B20_DIR)
-            .join(&self.address.0)
-            .join("temperature")
+    fn read(&self, previous: Option<f32>) -> Result<f32, SensorError> {
+        let raw_read =
+            utils::read_file_to_string(self.device_dir().join(self.config.file.file_name()))
+                .map_err(|err| SensorError::FileRead(err.to_string()))?;
+        let temp = match self.config.file {
+            Ds18b20File::W1Slave => parse_w1_slave(&raw_read)?,
+            Ds18b20File::Temperature => parse_temp_measurement(&raw_read)?,
+        };
+        let consistent = previous.is_some_and(|previous| {
+            (previous - POWER_ON_RESET_TEMP).abs() <= POWER_ON_RESET_TOLERANCE
+        });
+        if temp == POWER_ON_RESET_TEMP && !consistent {
+            return Err(SensorError::PowerOnReset);
+        }
+        Ok(temp)
     }
 }
 
@@ -43,47 +193,84 @@ impl Ds18b20 {
 ///
 /// Each sensor connected to the 1-wire bus is listed with its unique address in the same
 /// directory.
-/// Returns a vector with all filenames in that directory that parses as a [`Ds18b20Address`].
-pub fn list_available() -> Result<Vec<Ds18b20Address>, SensorError> {
-    println!("Finding sensors");
-    let device_path = Path::new(DS18B20_DIR);
-    if !device_path.exists() {
-        // TODO: Better error
+/// Returns a sorted vector with all filenames in that directory that parses as a
+/// [`Ds18b20Address`].
+pub fn list_available(one_wire_dir: &Path) -> Result<Vec<Ds18b20Address>, SensorError> {
+    if !one_wire_dir.exists() {
         return Err(SensorError::FileRead(format!(
             "DSB path does not exist: '{}'",
-            DS18B20_DIR
+            one_wire_dir.display()
         )));
     }
-    let files = match fs::read_dir(device_path) {
+    let files = match fs::read_dir(one_wire_dir) {
         Ok(files) => files,
-        Err(_error) => {
+        Err(err) => {
             return Err(SensorError::FileRead(format!(
-                "Unable to list DSB files {}.",
-                DS18B20_DIR
+                "Unable to list DSB files {}: {}",
+                one_wire_dir.display(),
+                err
             )))
         }
     };
-    Ok(files
+    let mut addresses: Vec<Ds18b20Address> = files
         .filter_map(Result::ok)
         .flat_map(ds18b20_address_from_filename)
+        .collect();
+    addresses.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(addresses)
+}
+
+/// A [`Ds18b20`] found on the 1-wire bus
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct DiscoveredDs18b20 {
+    pub address: Ds18b20Address,
+    /// Current reading, which fails e.g., for a badly connected sensor.
+    pub reading: Result<f32, SensorError>,
+}
+
+/// Discover the [`Ds18b20`] sensors on the 1-wire bus and take a reading from each
+///
+/// NB: Each reading blocks for the conversion time of the sensor, up to 750 ms.
+pub fn discover(one_wire_dir: &Path) -> Result<Vec<DiscoveredDs18b20>, SensorError> {
+    Ok(list_available(one_wire_dir)?
+        .into_iter()
+        .map(|address| {
+            let config = Ds18b20Config::with_address(address.clone());
+            let reading = Ds18b20::try_new(address.as_ref(), &config, one_wire_dir)
+                .and_then(|mut sensor| sensor.get_measurement());
+            DiscoveredDs18b20 { address, reading }
+        })
         .collect())
 }
 
 impl Sensor for Ds18b20 {
     /// Get DS18B20 temperature measurement
     ///
-    /// The sensor is available through a file located in [`DS18B20_DIR`].
+    /// The sensor is available through a file located in the 1-wire root, see [`DS18B20_DIR`].
     /// By simply reading the contents of the file a measurement is taken,
     /// from which a float value is parsed.
     ///
     /// The returned value is the temperature in Celsius.
     fn get_measurement(&mut self) -> Result<f32, SensorError> {
-        let meas = match utils::read_file_to_string(self.device_path()) {
-            Ok(raw_read) => parse_temp_measurement(&raw_read),
-            Err(err) => Err(SensorError::FileRead(err.to_string())),
-        };
-        sleep(Duration::from_millis(1000));
-        meas
+        // Settings are only attempted once, a sensor that rejects them is still read with its
+        // defaults. The failure is reported as the first measurement.
+        if !self.settings_applied {
+            self.settings_applied = true;
+            self.apply_settings()?;
+        }
+        // Only a valid measurement counts as history, 85°C is accepted if the previous valid
+        // measurement was close to it, never because a rejected retry read 85°C as well.
+        let mut res = self.read(self.last_valid);
+        for _ in 0..self.config.max_retries {
+            if res.is_ok() {
+                break;
+            }
+            res = self.read(self.last_valid);
+        }
+        if let Ok(temp) = res {
+            self.last_valid = Some(temp);
+        }
+        res
     }
 
     fn get_id(&self) -> String {
@@ -112,9 +299,8 @@ impl Ds18b20Address {
     ///
     /// Checks that the address starts with "28" and is exactly 15 characters.
     fn verify(address: &str) -> Result<(), SensorError> {
-        match &address[0..2] {
-            "28" => {}
-            _ => return Err(SensorError::InvalidAddressStart(String::from(address))),
+        if !address.starts_with("28") {
+            return Err(SensorError::InvalidAddressStart(String::from(address)));
         }
         match address.len() {
             15 => {}
@@ -141,6 +327,59 @@ impl fmt::Display for Ds18b20Address {
     }
 }
 
+/// Parse temperature from the contents of the `w1_slave` file, e.g.,
+/// ```text
+/// 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+/// 72 01 4b 46 7f ff 0e 10 57 t=23125
+/// ```
+fn parse_w1_slave(raw_read: &str) -> Result<f32, SensorError> {
+    let mut lines = raw_read.lines();
+    let (crc_line, temp_line) = match (lines.next(), lines.next()) {
+        (Some(crc_line), Some(temp_line)) => (crc_line, temp_line),
+        _ => {
+            return Err(SensorError::Parse(format!(
+                "Expected two lines, got '{}'",
+                raw_read
+            )))
+        }
+    };
+    let (scratchpad, crc_status) = crc_line
+        .split_once(':')
+        .ok_or_else(|| SensorError::Parse(format!("Missing CRC in '{}'", crc_line)))?;
+    let scratchpad = scratchpad
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|err| SensorError::Parse(format!("Invalid scratchpad '{}': {}", crc_line, err)))?;
+    if scratchpad.len() != 9 {
+        return Err(SensorError::Parse(format!(
+            "Expected 9 scratchpad bytes, got '{}'",
+            crc_line
+        )));
+    }
+    // An unresponsive sensor reads as all zeros, which passes the CRC.
+    if !crc_status.trim_end().ends_with("YES")
+        || crc8(&scratchpad[..8]) != scratchpad[8]
+        || scratchpad.iter().all(|byte| *byte == 0)
+    {
+        return Err(SensorError::Crc(String::from(crc_line)));
+    }
+    let (_, temp) = temp_line
+        .rsplit_once("t=")
+        .ok_or_else(|| SensorError::Parse(format!("Missing temperature in '{}'", temp_line)))?;
+    parse_temp_measurement(temp)
+}
+
+/// Dallas/Maxim 1-wire CRC-8
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ 0x8c,
+            _ => crc >> 1,
+        })
+    })
+}
+
 /// Parse float value from raw output generated by [`Ds18b20`] file read.
 fn parse_temp_measurement(raw_read: &str) -> Result<f32, SensorError> {
     let value: f32 = match raw_read.trim().parse() {
@@ -168,14 +407,14 @@ fn ds18b20_address_from_filename(file: fs::DirEntry) -> Option<Ds18b20Address> {
     }
 }
 
-/// Directory where the filesystem API registers DS18B20 sensors.
+/// Default directory where the filesystem API registers DS18B20 sensors.
 /// This is true for RbPi and probably for most linux systems.
 pub const DS18B20_DIR: &str = "/sys/bus/w1/devices/";
-// const DS18B20_DIR: &str = "./dummy_ds18";
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sensor::SensorType;
     use assert_approx_eq::assert_approx_eq;
     use std::matches;
 
@@ -203,6 +442,158 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_address_short() {
+        let address = Ds18b20Address::verify("2");
+        assert!(matches!(address, Err(SensorError::InvalidAddressStart(..))));
+    }
+
+    const W1_SLAVE: &str = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n\
+                            72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+    const W1_SLAVE_RESET: &str = "50 05 4b 46 7f ff 0c 10 1c : crc=1c YES\n\
+                                  50 05 4b 46 7f ff 0c 10 1c t=85000\n";
+
+    /// Fake 1-wire root with a device directory per entry, containing a file with the contents.
+    ///
+    /// The process id is part of the directory name, so that concurrent test runs do not clash.
+    fn fake_one_wire_dir(name: &str, devices: &[(&str, Option<(&str, &str)>)]) -> PathBuf {
+        let one_wire_dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&one_wire_dir);
+        for (dir, file) in devices {
+            let device_dir = one_wire_dir.join(dir);
+            fs::create_dir_all(&device_dir).unwrap();
+            if let Some((file, contents)) = file {
+                fs::write(device_dir.join(file), contents).unwrap();
+            }
+        }
+        one_wire_dir
+    }
+
+    #[test]
+    fn test_discover() {
+        let one_wire_dir = fake_one_wire_dir(
+            "bryggio_test_discover_w1",
+            &[
+                ("28-0416802230ff", Some(("w1_slave", W1_SLAVE))),
+                ("28-0316802230aa", Some(("w1_slave", "oops"))),
+                ("28-0516802230bb", None),
+                ("w1_bus_master1", None),
+            ],
+        );
+        let discovered = discover(&one_wire_dir).unwrap();
+        fs::remove_dir_all(&one_wire_dir).unwrap();
+
+        let addresses: Vec<&str> = discovered.iter().map(|dsb| dsb.address.as_ref()).collect();
+        assert_eq!(
+            addresses,
+            vec!["28-0316802230aa", "28-0416802230ff", "28-0516802230bb"]
+        );
+        assert!(matches!(discovered[0].reading, Err(SensorError::Parse(..))));
+        assert_approx_eq!(discovered[1].reading.clone().unwrap(), 23.125);
+        assert!(matches!(
+            discovered[2].reading,
+            Err(SensorError::FileRead(..))
+        ));
+        assert!(discover(&one_wire_dir).is_err());
+    }
+
+    #[test]
+    fn test_config_from_address() {
+        let parsed: SensorType = serde_json::from_str(r#"{"dsb": "28-0416802230ff"}"#).unwrap();
+        let address = Ds18b20Address::try_new("28-0416802230ff").unwrap();
+        assert_eq!(
+            parsed,
+            SensorType::Dsb(Ds18b20Config::with_address(address.clone()))
+        );
+        let parsed: SensorType = serde_json::from_str(
+            r#"{"dsb": {"address": "28-0416802230ff", "file": "temperature", "resolution": 10}}"#,
+        )
+        .unwrap();
+        let config = Ds18b20Config {
+            file: Ds18b20File::Temperature,
+            resolution: Some(10),
+            ..Ds18b20Config::with_address(address)
+        };
+        assert_eq!(parsed, SensorType::Dsb(config));
+    }
+
+    #[test]
+    fn test_parse_w1_slave() {
+        assert_approx_eq!(parse_w1_slave(W1_SLAVE).unwrap(), 23.125);
+        let bad_crc = W1_SLAVE.replace("10 57 :", "10 58 :");
+        assert!(matches!(
+            parse_w1_slave(&bad_crc),
+            Err(SensorError::Crc(..))
+        ));
+        let kernel_no = W1_SLAVE.replace("YES", "NO");
+        assert!(matches!(
+            parse_w1_slave(&kernel_no),
+            Err(SensorError::Crc(..))
+        ));
+        let zeros = "00 00 00 00 00 00 00 00 00 : crc=00 YES\n\
+                     00 00 00 00 00 00 00 00 00 t=0\n";
+        assert!(matches!(parse_w1_slave(zeros), Err(SensorError::Crc(..))));
+        assert!(matches!(
+            parse_w1_slave("72 01 4b"),
+            Err(SensorError::Parse(..))
+        ));
+    }
+
+    #[test]
+    fn test_power_on_reset() {
+        let address = "28-0416802230ff";
+        let one_wire_dir = fake_one_wire_dir(
+            "bryggio_test_power_on_reset_w1",
+            &[(address, Some(("w1_slave", W1_SLAVE_RESET)))],
+        );
+        let config = Ds18b20Config {
+            max_retries: 0,
+            resolution: Some(11),
+            ..Ds18b20Config::with_address(Ds18b20Address::try_new(address).unwrap())
+        };
+        let mut sensor = Ds18b20::try_new("mash", &config, &one_wire_dir).unwrap();
+        let first = sensor.get_measurement();
+        let resolution = fs::read_to_string(one_wire_dir.join(address).join("resolution"));
+        assert!(matches!(
+            sensor.read(Some(60.0)),
+            Err(SensorError::PowerOnReset)
+        ));
+        assert_approx_eq!(sensor.read(Some(84.5)).unwrap(), 85.0);
+        // A retry reading 85 again is no evidence of a real temperature.
+        sensor.config.max_retries = 1;
+        let retried = sensor.get_measurement();
+        sensor.last_valid = Some(84.5);
+        let consistent = sensor.get_measurement();
+        fs::remove_dir_all(&one_wire_dir).unwrap();
+
+        assert!(matches!(first, Err(SensorError::PowerOnReset)));
+        assert_eq!(resolution.unwrap(), "11");
+        assert!(matches!(retried, Err(SensorError::PowerOnReset)));
+        assert_approx_eq!(consistent.unwrap(), 85.0);
+    }
+
+    #[test]
+    fn test_settings_failure() {
+        let address = "28-0416802230ff";
+        let one_wire_dir = fake_one_wire_dir(
+            "bryggio_test_settings_failure_w1",
+            &[(address, Some(("w1_slave", W1_SLAVE)))],
+        );
+        // A directory in place of the settings file makes the write fail.
+        fs::create_dir_all(one_wire_dir.join(address).join("resolution")).unwrap();
+        let config = Ds18b20Config {
+            resolution: Some(11),
+            ..Ds18b20Config::with_address(Ds18b20Address::try_new(address).unwrap())
+        };
+        let mut sensor = Ds18b20::try_new("mash", &config, &one_wire_dir).unwrap();
+        let first = sensor.get_measurement();
+        let second = sensor.get_measurement();
+        fs::remove_dir_all(&one_wire_dir).unwrap();
+
+        assert!(matches!(first, Err(SensorError::FileWrite(..))));
+        assert_approx_eq!(second.unwrap(), 23.125);
+    }
+
     #[test]
     fn test_parse_temp_measurement_single_digit() {
         let temp_string = String::from("8720");
@@ -2,8 +2,6 @@
 use crate::sensor::{Sensor, SensorError};
 use rand::prelude::*;
 use rand_distr::{Distribution, Normal};
-use std::thread::sleep;
-use std::time::Duration;
 
 /// Generic dummy sensor
 ///
@@ -14,13 +12,11 @@ pub struct DummySensor {
     latest_value: f32,
     // TODO: Make distribution parametrised.
     //noise_level: f32,
-    /// Fake delay to simulate a real measurement.
-    delay: Duration,
     rng: Normal<f32>,
 }
 
 impl DummySensor {
-    pub fn new(id: &str, delay_in_ms: u64) -> DummySensor {
+    pub fn new(id: &str) -> DummySensor {
         let normal_distr = match Normal::new(0.0, 10.0) {
             Ok(normal) => normal,
             Err(err) => panic!("Dummy sensor normal rng: {:?}", err),
@@ -28,7 +24,6 @@ impl DummySensor {
         DummySensor {
             id: id.into(),
             latest_value: 50.0,
-            delay: Duration::from_millis(delay_in_ms),
             rng: normal_distr,
         }
     }
@@ -38,7 +33,6 @@ impl Sensor for DummySensor {
     fn get_measurement(&mut self) -> Result<f32, SensorError> {
         let measurement = self.latest_value + self.rng.sample(&mut thread_rng()) / 10.0;
         self.latest_value = measurement;
-        sleep(self.delay);
         Ok(measurement)
     }
     fn get_id(&self) -> String {
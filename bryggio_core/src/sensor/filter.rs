@@ -0,0 +1,211 @@
+//! Filters for sensor measurements
+//!
+//! The filters of a sensor are applied in order by the [`SensorClient`](super::SensorClient)
+//! before publishing, the unfiltered value is published on the raw subject,
+//! see [`SensorMsg::raw_subject`](super::SensorMsg::raw_subject).
+//! Failed readings pass through untouched and do not change the state of the filters.
+use crate::sensor::SensorError;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Mean of the last `window` values.
+    #[serde(rename = "moving_average")]
+    MovingAverage { window: usize },
+    /// `alpha * value + (1 - alpha) * previous`, a smaller `alpha` smooths more.
+    #[serde(rename = "exponential")]
+    Exponential { alpha: f32 },
+    /// Median of the last `window` values.
+    #[serde(rename = "median")]
+    Median { window: usize },
+    /// Reject values further than `max_jump` from the last accepted value.
+    /// After `max_rejections` rejections in a row the value is accepted, since the jump is real.
+    #[serde(rename = "spike_rejection")]
+    SpikeRejection {
+        max_jump: f32,
+        #[serde(default = "default_max_rejections")]
+        max_rejections: u32,
+    },
+    /// Limit the change between two values to `max_rate_per_s` times the time between them.
+    #[serde(rename = "rate_limit")]
+    RateLimit { max_rate_per_s: f32 },
+}
+
+fn default_max_rejections() -> u32 {
+    3
+}
+
+impl Filter {
+    pub fn validate(&self) -> Result<(), SensorError> {
+        let valid = match *self {
+            Filter::MovingAverage { window } | Filter::Median { window } => window > 0,
+            Filter::Exponential { alpha } => alpha > 0.0 && alpha <= 1.0,
+            Filter::SpikeRejection { max_jump, .. } => max_jump > 0.0,
+            Filter::RateLimit { max_rate_per_s } => max_rate_per_s > 0.0,
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(SensorError::InvalidParam(format!(
+                "Invalid filter {:?}",
+                self
+            ))),
+        }
+    }
+}
+
+/// A filter with its state.
+struct Stage {
+    filter: Filter,
+    values: VecDeque<f32>,
+    previous: Option<(TimeStamp, f32)>,
+    rejections: u32,
+}
+
+impl Stage {
+    fn apply(&mut self, value: f32, timestamp: TimeStamp) -> Result<f32, SensorError> {
+        let filtered = match self.filter {
+            Filter::MovingAverage { window } => {
+                self.push(value, window);
+                self.values.iter().sum::<f32>() / self.values.len() as f32
+            }
+            Filter::Median { window } => {
+                self.push(value, window);
+                let mut sorted: Vec<f32> = self.values.iter().copied().collect();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                let mid = sorted.len() / 2;
+                match sorted.len() % 2 {
+                    0 => (sorted[mid - 1] + sorted[mid]) / 2.0,
+                    _ => sorted[mid],
+                }
+            }
+            Filter::Exponential { alpha } => match self.previous {
+                Some((_, previous)) => alpha * value + (1.0 - alpha) * previous,
+                None => value,
+            },
+            Filter::SpikeRejection {
+                max_jump,
+                max_rejections,
+            } => match self.previous {
+                Some((_, previous))
+                    if (value - previous).abs() > max_jump && self.rejections < max_rejections =>
+                {
+                    self.rejections += 1;
+                    return Err(SensorError::Outlier(format!(
+                        "{} is more than {} from {}",
+                        value, max_jump, previous
+                    )));
+                }
+                _ => value,
+            },
+            Filter::RateLimit { max_rate_per_s } => match self.previous {
+                Some((prev_timestamp, previous)) => {
+                    let dt = timestamp.0.saturating_sub(prev_timestamp.0) as f32 / 1000.0;
+                    let max_change = max_rate_per_s * dt;
+                    value.clamp(previous - max_change, previous + max_change)
+                }
+                None => value,
+            },
+        };
+        self.rejections = 0;
+        self.previous = Some((timestamp, filtered));
+        Ok(filtered)
+    }
+
+    fn push(&mut self, value: f32, window: usize) {
+        self.values.push_back(value);
+        while self.values.len() > window {
+            self.values.pop_front();
+        }
+    }
+}
+
+pub struct FilterChain {
+    stages: Vec<Stage>,
+}
+
+impl FilterChain {
+    /// The filters are expected to be validated, see [`Filter::validate`].
+    pub fn new(filters: &[Filter]) -> FilterChain {
+        let stages = filters
+            .iter()
+            .map(|filter| Stage {
+                filter: filter.clone(),
+                values: VecDeque::new(),
+                previous: None,
+                rejections: 0,
+            })
+            .collect();
+        FilterChain { stages }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub fn apply(
+        &mut self,
+        meas: Result<f32, SensorError>,
+        timestamp: TimeStamp,
+    ) -> Result<f32, SensorError> {
+        self.stages
+            .iter_mut()
+            .try_fold(meas?, |value, stage| stage.apply(value, timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn chain(filters: &str) -> FilterChain {
+        let filters: Vec<Filter> = serde_json::from_str(filters).unwrap();
+        for filter in filters.iter() {
+            filter.validate().unwrap();
+        }
+        FilterChain::new(&filters)
+    }
+
+    fn run(chain: &mut FilterChain, values: &[f32]) -> Vec<Result<f32, SensorError>> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| chain.apply(Ok(*value), TimeStamp(idx as u128 * 1000)))
+            .collect()
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut moving_average = chain(r#"[{"moving_average": {"window": 2}}]"#);
+        let values = run(&mut moving_average, &[60.0, 62.0, 66.0]);
+        assert_approx_eq!(values[2].clone().unwrap(), 64.0);
+        let mut median = chain(r#"[{"median": {"window": 3}}]"#);
+        let values = run(&mut median, &[60.0, 90.0, 61.0]);
+        assert_approx_eq!(values[2].clone().unwrap(), 61.0);
+        let mut exponential = chain(r#"[{"exponential": {"alpha": 0.25}}]"#);
+        let values = run(&mut exponential, &[60.0, 64.0]);
+        assert_approx_eq!(values[1].clone().unwrap(), 61.0);
+    }
+
+    #[test]
+    fn test_spike_rejection() {
+        let mut chain = chain(r#"[{"spike_rejection": {"max_jump": 5.0, "max_rejections": 2}}]"#);
+        let values = run(&mut chain, &[60.0, 85.0, 60.5, 70.0, 70.0, 70.0]);
+        assert!(matches!(values[1], Err(SensorError::Outlier(..))));
+        assert_approx_eq!(values[2].clone().unwrap(), 60.5);
+        assert!(values[3].is_err() && values[4].is_err());
+        assert_approx_eq!(values[5].clone().unwrap(), 70.0);
+    }
+
+    #[test]
+    fn test_rate_limit_and_errors() {
+        let mut chain = chain(r#"[{"rate_limit": {"max_rate_per_s": 0.5}}]"#);
+        let values = run(&mut chain, &[60.0, 70.0, 50.0]);
+        assert_approx_eq!(values[1].clone().unwrap(), 60.5);
+        assert_approx_eq!(values[2].clone().unwrap(), 60.0);
+        let err = SensorError::FileRead(String::from("gone"));
+        assert_eq!(chain.apply(Err(err.clone()), TimeStamp(3000)), Err(err));
+    }
+}
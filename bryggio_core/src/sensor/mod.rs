@@ -1,16 +1,25 @@
 //! General sensor logic
+pub mod calibration;
 pub mod cpu_temp;
 pub mod ds18b20;
 pub mod dummy;
+pub mod filter;
 mod pub_sub;
+pub mod sampling;
 use crate::pub_sub::{ClientId, PubSubError};
-pub use crate::sensor::pub_sub::{SensorClient, SensorMsg};
+pub use crate::sensor::calibration::Calibration;
+pub use crate::sensor::filter::Filter;
+pub use crate::sensor::pub_sub::{SensorClient, SensorCommand, SensorMsg};
+pub use crate::sensor::sampling::Sampling;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 use thiserror::Error;
 
 /// Common sensor interface
 pub trait Sensor: Send {
-    /// Make a reading from a sensor
+    /// Make a single reading from a sensor
+    ///
+    /// Pacing is up to the [`SensorClient`], see [`Sampling`].
     fn get_measurement(&mut self) -> Result<f32, SensorError>;
     /// Return unique internal ID
     fn get_id(&self) -> String;
@@ -19,12 +28,15 @@ pub trait Sensor: Send {
 /// Sensor type list
 ///
 /// Helper type for creating sensors at runtime using [`SensorConfig::create_sensor`]
+///
+/// The delay of `Dummy` and `RbpiCpu` is the sampling period used when
+/// [`SensorConfig::sampling`] is not set.
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub enum SensorType {
     #[serde(rename = "dummy")]
     Dummy(u64),
-    #[serde(rename = "dsb")]
-    Dsb(ds18b20::Ds18b20Address),
+    #[serde(rename = "dsb", deserialize_with = "ds18b20::deserialize_config")]
+    Dsb(ds18b20::Ds18b20Config),
     #[serde(rename = "rbpi_cpu")]
     RbpiCpu(u64),
 }
@@ -32,31 +44,59 @@ pub enum SensorType {
 /// Sensor config
 ///
 /// Helper type for creating sensors at runtime using [`SensorConfig::create_sensor`]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SensorConfig {
     /// Pub-sub ID, must be unique.
     pub id: ClientId,
     #[serde(rename = "type")]
     pub type_: SensorType,
+    #[serde(default)]
+    pub sampling: Option<Sampling>,
+    /// Applied to each measurement before the filters, see [`calibration`].
+    #[serde(default)]
+    pub calibration: Option<Calibration>,
+    /// Applied in order to each measurement before publishing, see [`filter`].
+    #[serde(default)]
+    pub filters: Vec<Filter>,
 }
 
 impl SensorConfig {
+    /// The configured sampling, or the default sampling for the sensor type.
+    pub fn sampling(&self) -> Sampling {
+        match (self.sampling, &self.type_) {
+            (Some(sampling), _) => sampling,
+            (None, SensorType::Dummy(period_ms)) | (None, SensorType::RbpiCpu(period_ms)) => {
+                Sampling::with_period(*period_ms)
+            }
+            (None, SensorType::Dsb(_)) => Sampling::default(),
+        }
+    }
+
     /// Create sensor at runtime with dynamic dispatch.
     ///
     /// The type of the sensors specified in e.g., config files cannot be known at runtime,
     /// therefore are we forced to use dynamic dispatch.
-    pub fn create_sensor(&self) -> Result<Box<dyn Sensor>, SensorError> {
+    ///
+    /// DS18B20 sensors are looked up in `one_wire_dir`, see [`ds18b20`].
+    pub fn create_sensor(&self, one_wire_dir: &Path) -> Result<Box<dyn Sensor>, SensorError> {
+        self.sampling().validate()?;
+        if let Some(calibration) = &self.calibration {
+            calibration.validate()?;
+        }
+        for filter in self.filters.iter() {
+            filter.validate()?;
+        }
         match &self.type_ {
-            SensorType::Dummy(delay_in_ms) => {
-                let sensor = dummy::DummySensor::new(self.id.as_ref(), *delay_in_ms);
+            SensorType::Dummy(_) => {
+                let sensor = dummy::DummySensor::new(self.id.as_ref());
                 Ok(Box::new(sensor))
             }
-            SensorType::Dsb(addr) => {
-                let sensor = ds18b20::Ds18b20::try_new(self.id.as_ref(), addr.as_ref())?;
+            SensorType::Dsb(dsb_config) => {
+                let sensor = ds18b20::Ds18b20::try_new(self.id.as_ref(), dsb_config, one_wire_dir)?;
                 Ok(Box::new(sensor))
             }
-            SensorType::RbpiCpu(delay_in_ms) => {
-                let sensor = cpu_temp::CpuTemp::new(self.id.as_ref(), *delay_in_ms);
+            SensorType::RbpiCpu(_) => {
+                let sensor = cpu_temp::CpuTemp::new(self.id.as_ref());
                 Ok(Box::new(sensor))
             }
         }
@@ -71,6 +111,12 @@ pub enum SensorError {
     InvalidAddressLength(usize),
     #[error("Unable to read from file: {0}")]
     FileRead(String),
+    #[error("Unable to write to file: {0}")]
+    FileWrite(String),
+    #[error("CRC check failed: {0}")]
+    Crc(String),
+    #[error("Read the 85°C power-on reset value")]
+    PowerOnReset,
     #[error("Could not parse value: {0}")]
     Parse(String),
     #[error("Unable to acquire sensor lock: {0}")]
@@ -79,6 +125,10 @@ pub enum SensorError {
     InvalidParam(String),
     #[error("Unknown sensor: {0}")]
     UnknownSensor(String),
+    #[error("Measurement rejected as outlier: {0}")]
+    Outlier(String),
+    #[error("Calibration failed: {0}")]
+    Calibration(String),
 }
 
 impl From<SensorError> for PubSubError {
@@ -4,17 +4,27 @@
 //! It provides a generic type [`SensorClient`] which wraps any type that implements the [`Sensor`]
 //! trait and provides methods for publishing measurments to the pub-sub server and subscribing to
 //! commands from other clients in the network.
+//!
+//! Sensors are calibrated live with [`SensorCommand`]s on `command.sensor.<id>`:
+//! capture a reference point for each reference temperature, then finish to get the
+//! [`Calibration`] through the points, which is to be added to the sensor config.
 
-use crate::logger::info;
+use crate::logger::{info, warning};
 use crate::pub_sub::{
-    nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsClientConfig,
-    ClientId, MessageParseError, PubSubClient, PubSubError, PubSubMsg, Subject,
+    heartbeat::Heartbeat, nats_client::decode_nats_data, nats_client::NatsClient,
+    nats_client::NatsClientConfig, ClientId, ClientState, MessageParseError, PubSubClient,
+    PubSubError, PubSubMsg, Subject,
 };
-use crate::sensor::{Sensor, SensorError};
-use crate::time::TimeStamp;
+use crate::sensor::calibration::{Calibration, ReferencePoint};
+use crate::sensor::filter::FilterChain;
+use crate::sensor::sampling::{Sampler, Sampling};
+use crate::sensor::{Sensor, SensorConfig, SensorError};
+use crate::supervisor::pub_sub::SupervisorPubMsg;
+use crate::time::{TimeStamp, LOOP_PAUSE_TIME};
 use nats::{Message, Subscription};
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::thread::sleep;
 
 /// Generic pub-sub sensor client
 pub struct SensorClient {
@@ -22,13 +32,67 @@ pub struct SensorClient {
     id: ClientId,
     /// Any type that implements the [`Sensor`] trait.
     sensor: Box<dyn Sensor>,
+    sampling: Sampling,
+    calibration: Option<Calibration>,
+    filters: FilterChain,
+    /// Last measurement before calibration, captured by [`SensorCommand::CaptureReference`].
+    last_measured: Option<f32>,
+    reference_points: Vec<ReferencePoint>,
     client: NatsClient,
 }
 
 impl SensorClient {
-    pub fn new(id: ClientId, sensor: Box<dyn Sensor>, config: &NatsClientConfig) -> Self {
+    pub fn new(
+        sensor_config: &SensorConfig,
+        sensor: Box<dyn Sensor>,
+        config: &NatsClientConfig,
+    ) -> Self {
         let client = NatsClient::try_new(config).unwrap();
-        SensorClient { id, sensor, client }
+        SensorClient {
+            id: sensor_config.id.clone(),
+            sensor,
+            sampling: sensor_config.sampling(),
+            calibration: sensor_config.calibration.clone(),
+            filters: FilterChain::new(&sensor_config.filters),
+            last_measured: None,
+            reference_points: Vec::new(),
+            client,
+        }
+    }
+
+    fn process_command(&mut self, cmd: SensorCommand) -> Result<String, SensorError> {
+        match cmd {
+            SensorCommand::CaptureReference { reference } => {
+                let measured = self.last_measured.ok_or_else(|| {
+                    SensorError::Calibration(String::from("No valid measurement to capture"))
+                })?;
+                self.reference_points.push(ReferencePoint {
+                    measured,
+                    reference,
+                });
+                Ok(format!(
+                    "Captured {} at reference {} for sensor '{}', {} point(s) so far",
+                    measured,
+                    reference,
+                    self.id,
+                    self.reference_points.len()
+                ))
+            }
+            SensorCommand::FinishCalibration => {
+                let calibration = Calibration::fit(&self.reference_points)?;
+                self.reference_points.clear();
+                info(
+                    self,
+                    format!("Calibration of sensor '{}': {:?}", self.id, calibration),
+                    &format!("sensor.{}", self.id),
+                );
+                Ok(serde_json::to_string(&calibration).expect("Can always serialize"))
+            }
+            SensorCommand::AbortCalibration => {
+                self.reference_points.clear();
+                Ok(format!("Calibration of sensor '{}' aborted", self.id))
+            }
+        }
     }
 
     fn meas_subject(&self) -> Subject {
@@ -47,6 +111,42 @@ impl SensorMsg {
     pub fn subject(id: &ClientId) -> Subject {
         Subject(format!("sensor.{}.measurement", id))
     }
+
+    /// Calibrated but unfiltered measurements, only published by sensors with filters.
+    pub fn raw_subject(id: &ClientId) -> Subject {
+        Subject(format!("sensor.{}.raw", id))
+    }
+}
+
+/// Commands on `command.sensor.<id>`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SensorCommand {
+    /// Pair the current measurement with the temperature of a reference thermometer.
+    #[serde(rename = "capture_reference")]
+    CaptureReference { reference: f32 },
+    /// Reply with the calibration through the captured points, see [`Calibration::fit`].
+    #[serde(rename = "finish_calibration")]
+    FinishCalibration,
+    #[serde(rename = "abort_calibration")]
+    AbortCalibration,
+}
+
+impl SensorCommand {
+    pub fn subject(id: &ClientId) -> Subject {
+        Subject(format!("command.sensor.{}", id))
+    }
+
+    /// Whether the subject is a command to a sensor rather than to the supervisor.
+    pub fn is_sensor_command(subject: &str) -> bool {
+        subject.starts_with("command.sensor.")
+    }
+}
+
+impl TryFrom<&Message> for SensorCommand {
+    type Error = MessageParseError;
+    fn try_from(msg: &Message) -> Result<Self, Self::Error> {
+        decode_nats_data(&msg.data)
+    }
 }
 
 impl From<SensorMsg> for PubSubMsg {
@@ -69,14 +169,86 @@ impl PubSubClient for SensorClient {
             format!("Starting sensor with id '{}'", self.id),
             &format!("sensor.{}", self.id),
         );
-        let supervisor = self.subscribe(&Subject(format!("command.sensor.{}", self.id)))?;
+        let commands = self.subscribe(&SensorCommand::subject(&self.id))?;
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: self.id.clone(),
+            }
+            .subject(),
+        )?;
         let meas_sub = self.meas_subject();
+        let raw_sub = SensorMsg::raw_subject(&self.id);
+        let mut heartbeat = Heartbeat::new("sensor", &self.id);
+        let mut sampler = Sampler::new(self.sampling, TimeStamp::now());
         loop {
-            for _msg in supervisor.try_iter() {
-                // Deal with supervisor command
+            if let Some(msg) = kill_cmd.try_next() {
+                msg.respond(serde_json::to_string(&()).expect("Can always serialize"))
+                    .map_err(|err| PubSubError::Reply {
+                        task: "kill sensor",
+                        msg: msg.clone(),
+                        source: err,
+                    })?;
+                info(
+                    &self,
+                    format!("Sensor '{}' killed", self.id),
+                    &format!("sensor.{}", self.id),
+                );
+                return Ok(());
             }
-            let meas = self.sensor.get_measurement();
+            for msg in commands.try_iter() {
+                let response = match SensorCommand::try_from(&msg) {
+                    Ok(cmd) => match self.process_command(cmd) {
+                        Ok(response) => response,
+                        Err(err) => err.to_string(),
+                    },
+                    Err(err) => err.to_string(),
+                };
+                if msg.reply.is_some() {
+                    msg.respond(response).map_err(|err| PubSubError::Reply {
+                        task: "sensor command",
+                        msg: msg.clone(),
+                        source: err,
+                    })?;
+                }
+            }
+            heartbeat.beat(&self, ClientState::Active);
+            let now = TimeStamp::now();
+            if !sampler.is_due(now) {
+                sleep(sampler.wait(now).min(LOOP_PAUSE_TIME));
+                continue;
+            }
+            if let Some(late_ms) = sampler.late_by_ms(now) {
+                let err_msg = format!(
+                    "Reading {} ms late, the sensor can not keep up with the sampling",
+                    late_ms
+                );
+                heartbeat.set_error(&err_msg);
+                warning(&self, err_msg, &format!("sensor.{}", self.id));
+            }
+            let reading = self.sensor.get_measurement();
             let timestamp = TimeStamp::now();
+            let meas = match sampler.add(reading, now) {
+                Some(meas) => meas,
+                None => continue,
+            };
+            self.last_measured = meas.as_ref().ok().copied();
+            let meas = match &self.calibration {
+                Some(calibration) => meas.map(|value| calibration.apply(value)),
+                None => meas,
+            };
+            if !self.filters.is_empty() {
+                let raw_msg = SensorMsg {
+                    id: self.id.clone(),
+                    timestamp,
+                    meas: meas.clone(),
+                };
+                self.publish(&raw_sub, &raw_msg.into())?;
+            }
+            let meas = self.filters.apply(meas, timestamp);
+            match &meas {
+                Ok(_) => heartbeat.clear_error(),
+                Err(err) => heartbeat.set_error(err),
+            }
             // debug(
             //     &self,
             //     format!("Sensor {}: {:?}", self.id, &meas),
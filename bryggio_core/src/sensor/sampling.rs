@@ -0,0 +1,191 @@
+//! Sampling policy for sensors
+//!
+//! The [`SensorClient`](super::SensorClient) takes `samples` readings spread evenly over each
+//! `period_ms`, and publishes their average once the last one is taken.
+//! Readings that fail are left out of the average, the period only fails if all readings do.
+//!
+//! A reading is late when it is taken more than `jitter_tolerance_ms` after it was due,
+//! typically because the sensor is slower than the sampling asks for.
+//! The schedule then restarts from the late reading rather than trying to catch up.
+use crate::sensor::SensorError;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampling {
+    #[serde(default = "default_period_ms")]
+    pub period_ms: u64,
+    /// Number of readings averaged into each published measurement.
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+    #[serde(default = "default_jitter_tolerance_ms")]
+    pub jitter_tolerance_ms: u64,
+}
+
+fn default_period_ms() -> u64 {
+    1000
+}
+
+fn default_samples() -> u32 {
+    1
+}
+
+fn default_jitter_tolerance_ms() -> u64 {
+    200
+}
+
+impl Sampling {
+    /// One reading per period.
+    pub fn with_period(period_ms: u64) -> Sampling {
+        Sampling {
+            period_ms,
+            samples: default_samples(),
+            jitter_tolerance_ms: default_jitter_tolerance_ms(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), SensorError> {
+        if self.period_ms == 0 || self.samples == 0 {
+            return Err(SensorError::InvalidParam(format!(
+                "period_ms and samples must be positive, got {} and {}",
+                self.period_ms, self.samples
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling::with_period(default_period_ms())
+    }
+}
+
+pub struct Sampler {
+    config: Sampling,
+    period_start: TimeStamp,
+    readings: Vec<Result<f32, SensorError>>,
+}
+
+impl Sampler {
+    pub fn new(config: Sampling, now: TimeStamp) -> Sampler {
+        Sampler {
+            config,
+            period_start: now,
+            readings: Vec::new(),
+        }
+    }
+
+    fn next_reading(&self) -> TimeStamp {
+        let offset = u128::from(self.config.period_ms) * self.readings.len() as u128
+            / u128::from(self.config.samples);
+        TimeStamp(self.period_start.0 + offset)
+    }
+
+    pub fn is_due(&self, now: TimeStamp) -> bool {
+        now >= self.next_reading()
+    }
+
+    /// Time left until the next reading is due.
+    pub fn wait(&self, now: TimeStamp) -> Duration {
+        let wait_ms = self.next_reading().0.saturating_sub(now.0);
+        Duration::from_millis(wait_ms as u64)
+    }
+
+    /// How much later than allowed the due reading is taken, if at all.
+    pub fn late_by_ms(&self, now: TimeStamp) -> Option<u128> {
+        let late_ms = now.0.saturating_sub(self.next_reading().0);
+        if late_ms > u128::from(self.config.jitter_tolerance_ms) {
+            Some(late_ms)
+        } else {
+            None
+        }
+    }
+
+    /// Add a reading taken at `now`, returns the measurement to publish once the period is done.
+    pub fn add(
+        &mut self,
+        reading: Result<f32, SensorError>,
+        now: TimeStamp,
+    ) -> Option<Result<f32, SensorError>> {
+        if self.late_by_ms(now).is_some() {
+            // Restart the schedule from this reading, keeping the readings of the period.
+            let offset = self.next_reading().0 - self.period_start.0;
+            self.period_start = TimeStamp(now.0 - offset);
+        }
+        self.readings.push(reading);
+        if self.readings.len() < self.config.samples as usize {
+            return None;
+        }
+        self.period_start = TimeStamp(self.period_start.0 + u128::from(self.config.period_ms));
+        Some(average(self.readings.drain(..)))
+    }
+}
+
+/// Mean of the valid readings, or the last error if there are none.
+fn average(readings: impl Iterator<Item = Result<f32, SensorError>>) -> Result<f32, SensorError> {
+    let mut sum = 0.0;
+    let mut count = 0;
+    let mut last_err = None;
+    for reading in readings {
+        match reading {
+            Ok(value) => {
+                sum += value;
+                count += 1;
+            }
+            Err(err) => last_err = Some(err),
+        }
+    }
+    match (count, last_err) {
+        (0, Some(err)) => Err(err),
+        (0, None) => Err(SensorError::InvalidParam(String::from("No readings"))),
+        _ => Ok(sum / count as f32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn sampler() -> Sampler {
+        let config: Sampling =
+            serde_json::from_str(r#"{"period_ms": 1000, "samples": 4}"#).unwrap();
+        config.validate().unwrap();
+        Sampler::new(config, TimeStamp(0))
+    }
+
+    #[test]
+    fn test_readings_spread_over_period() {
+        let mut sampler = sampler();
+        assert!(sampler.is_due(TimeStamp(0)));
+        assert_eq!(sampler.add(Ok(20.0), TimeStamp(0)), None);
+        assert!(!sampler.is_due(TimeStamp(200)));
+        assert_eq!(sampler.wait(TimeStamp(200)), Duration::from_millis(50));
+        sampler.add(Ok(21.0), TimeStamp(250));
+        sampler.add(Err(SensorError::Parse(String::from("crc"))), TimeStamp(500));
+        let meas = sampler.add(Ok(22.0), TimeStamp(750)).unwrap();
+        assert_approx_eq!(meas.unwrap(), 21.0);
+        assert!(sampler.is_due(TimeStamp(1000)));
+    }
+
+    #[test]
+    fn test_late_reading_restarts_schedule() {
+        let mut sampler = sampler();
+        sampler.add(Ok(20.0), TimeStamp(0));
+        assert_eq!(sampler.late_by_ms(TimeStamp(400)), None);
+        assert_eq!(sampler.late_by_ms(TimeStamp(600)), Some(350));
+        sampler.add(Ok(20.0), TimeStamp(600));
+        assert!(!sampler.is_due(TimeStamp(800)));
+        assert!(sampler.is_due(TimeStamp(850)));
+    }
+
+    #[test]
+    fn test_all_readings_failed() {
+        let config = Sampling::with_period(1000);
+        let mut sampler = Sampler::new(config, TimeStamp(0));
+        let err = SensorError::FileRead(String::from("gone"));
+        assert_eq!(sampler.add(Err(err.clone()), TimeStamp(0)), Some(Err(err)));
+    }
+}
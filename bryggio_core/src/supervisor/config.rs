@@ -3,9 +3,11 @@ use crate::logger::LogLevel;
 use crate::pub_sub::nats_client::Authorization;
 use crate::pub_sub::nats_client::{NatsServerConfig, WebSocket};
 use crate::pub_sub::ClientId;
-use crate::sensor::{SensorConfig, SensorType};
+use crate::safety::SafetyConfig;
+use crate::sensor::{ds18b20::DS18B20_DIR, SensorConfig, SensorType};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::io::Read;
 use std::path::{Path, PathBuf};
@@ -18,6 +20,12 @@ pub struct SupervisorConfig {
     pub general: General,
     pub hardware: Hardware,
     pub nats: NatsConfig,
+    pub restart: RestartConfig,
+    pub session: SessionConfig,
+    pub safety: SafetyConfig,
+    /// File the config was read from, used when reloading the config.
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
 }
 
 impl SupervisorConfig {
@@ -33,7 +41,8 @@ impl SupervisorConfig {
         };
         let conf_presumptive: ParseSupervisorConfig = serde_json::from_str(&config_string)
             .map_err(|err| SupervisorConfigError::Parse(err.to_string()))?;
-        let conf_presumptive = SupervisorConfig::from(conf_presumptive);
+        let mut conf_presumptive = SupervisorConfig::from(conf_presumptive);
+        conf_presumptive.config_file = Some(config_file.to_path_buf());
         SupervisorConfig::validate(conf_presumptive)
     }
 
@@ -42,6 +51,10 @@ impl SupervisorConfig {
             general: General::default(),
             nats: NatsConfig::dummy(),
             hardware: Hardware::dummy(),
+            restart: RestartConfig::default(),
+            session: SessionConfig::default(),
+            safety: SafetyConfig::default(),
+            config_file: None,
         }
     }
 
@@ -51,6 +64,7 @@ impl SupervisorConfig {
                 "Non-unique client IDs",
             )));
         }
+        pres.validate_guards()?;
         if !pres.nats.bin_path.as_path().exists() {
             return Err(SupervisorConfigError::Config(format!(
                 "NATS server bin '{}' missing",
@@ -59,6 +73,35 @@ impl SupervisorConfig {
         };
         Ok(pres)
     }
+
+    /// Every safety guard must refer to an actor and a sensor in the hardware config.
+    fn validate_guards(&self) -> Result<(), SupervisorConfigError> {
+        for guard in &self.safety.guards {
+            if !self
+                .hardware
+                .actors
+                .iter()
+                .any(|actor| actor.id == guard.actor_id)
+            {
+                return Err(SupervisorConfigError::Config(format!(
+                    "Safety guard for unknown actor '{}'",
+                    guard.actor_id
+                )));
+            }
+            if !self
+                .hardware
+                .sensors
+                .iter()
+                .any(|sensor| sensor.id == guard.sensor_id)
+            {
+                return Err(SupervisorConfigError::Config(format!(
+                    "Safety guard of actor '{}' uses unknown sensor '{}'",
+                    guard.actor_id, guard.sensor_id
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl From<ParseSupervisorConfig> for SupervisorConfig {
@@ -66,6 +109,10 @@ impl From<ParseSupervisorConfig> for SupervisorConfig {
         Self {
             general: parse.general.clone(),
             hardware: parse.hardware.clone(),
+            restart: parse.restart.clone(),
+            session: parse.session.clone(),
+            safety: parse.safety.clone(),
+            config_file: None,
             nats: NatsConfig::from_parsed(parse),
         }
     }
@@ -76,6 +123,9 @@ pub struct General {
     pub brewery_name: String,
     // TODO: Rename all
     pub log_level: LogLevel,
+    /// Time without heartbeats after which a client is flagged as overdue.
+    #[serde(default = "default_heartbeat_timeout_ms")]
+    pub heartbeat_timeout_ms: u64,
 }
 
 impl Default for General {
@@ -83,14 +133,26 @@ impl Default for General {
         General {
             brewery_name: "No name".into(),
             log_level: LogLevel::Info,
+            heartbeat_timeout_ms: default_heartbeat_timeout_ms(),
         }
     }
 }
 
+fn default_heartbeat_timeout_ms() -> u64 {
+    10_000
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Hardware {
     pub actors: Vec<ActorConfig>,
     pub sensors: Vec<SensorConfig>,
+    /// Root of the 1-wire devices, where DS18B20 sensors are found.
+    #[serde(default = "default_one_wire_dir")]
+    pub one_wire_dir: PathBuf,
+}
+
+fn default_one_wire_dir() -> PathBuf {
+    PathBuf::from(DS18B20_DIR)
 }
 
 impl Hardware {
@@ -99,6 +161,9 @@ impl Hardware {
             sensors: vec![SensorConfig {
                 id: ClientId("dummy_sensor".into()),
                 type_: SensorType::Dummy(1000),
+                sampling: None,
+                calibration: None,
+                filters: Vec::new(),
             }],
             actors: vec![ActorConfig {
                 id: ClientId("dummy_actor".into()),
@@ -107,6 +172,7 @@ impl Hardware {
                     time_out: None,
                 },
             }],
+            one_wire_dir: default_one_wire_dir(),
         }
     }
 
@@ -120,6 +186,103 @@ impl Hardware {
             .count();
         unique_count == self.sensors.len() + self.actors.len()
     }
+
+    /// Record a sensor started outside of a reload, replacing any config with the same id.
+    pub(crate) fn track_sensor(&mut self, config: SensorConfig) {
+        self.forget(&config.id);
+        self.sensors.push(config);
+    }
+
+    /// Record an actor started outside of a reload, replacing any config with the same id.
+    pub(crate) fn track_actor(&mut self, config: ActorConfig) {
+        self.forget(&config.id);
+        self.actors.push(config);
+    }
+
+    /// Drop the config of a sensor or actor that was removed.
+    pub(crate) fn forget(&mut self, id: &ClientId) {
+        self.sensors.retain(|config| &config.id != id);
+        self.actors.retain(|config| &config.id != id);
+    }
+}
+
+/// Restart policies for clients started by the supervisor
+///
+/// Clients without an explicit entry in `clients` use the `default` policy.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RestartConfig {
+    #[serde(default)]
+    pub default: RestartPolicy,
+    #[serde(default)]
+    pub clients: HashMap<ClientId, RestartPolicy>,
+}
+
+impl RestartConfig {
+    pub fn policy(&self, id: &ClientId) -> RestartPolicy {
+        self.clients.get(id).copied().unwrap_or(self.default)
+    }
+}
+
+/// What the supervisor should do when a client thread exits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestartPolicy {
+    /// Leave the client stopped.
+    #[default]
+    #[serde(rename = "never")]
+    Never,
+    /// Restart the client if it exited with an error or panicked.
+    ///
+    /// The delay before a restart is doubled for every previous restart of the client,
+    /// starting at `initial_backoff_ms` and capped at `max_backoff_ms`.
+    #[serde(rename = "on_failure")]
+    OnFailure {
+        initial_backoff_ms: u64,
+        max_backoff_ms: u64,
+    },
+    /// Restart the client whenever it exits, after the minimum delay.
+    #[serde(rename = "always")]
+    Always,
+}
+
+/// Lower bound of every restart delay, so that a client failing to start is not retried in a
+/// tight loop.
+pub const MIN_RESTART_DELAY_MS: u64 = 500;
+
+impl RestartPolicy {
+    /// Delay before restarting a client, `None` if it should not be restarted.
+    ///
+    /// `attempts` is the number of restarts tried so far, including those that failed to start
+    /// the client.
+    pub fn restart_delay(&self, failed: bool, attempts: u32) -> Option<u64> {
+        let delay = match *self {
+            RestartPolicy::Never => None,
+            RestartPolicy::OnFailure { .. } if !failed => None,
+            RestartPolicy::OnFailure {
+                initial_backoff_ms,
+                max_backoff_ms,
+            } => {
+                let factor = 2u64.saturating_pow(attempts);
+                Some(
+                    initial_backoff_ms
+                        .saturating_mul(factor)
+                        .min(max_backoff_ms),
+                )
+            }
+            RestartPolicy::Always => Some(0),
+        };
+        delay.map(|delay| delay.max(MIN_RESTART_DELAY_MS))
+    }
+}
+
+/// Persistence of active controllers, see [`crate::supervisor::session`]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SessionConfig {
+    /// File to store the session in, the session is not persisted if missing.
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+    /// Resume the stored controllers when the supervisor starts.
+    #[serde(default)]
+    pub resume_on_start: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -149,6 +312,12 @@ struct ParseSupervisorConfig {
     pub general: General,
     pub hardware: Hardware,
     pub nats: ParseNatsServerConfig,
+    #[serde(default)]
+    pub restart: RestartConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub safety: SafetyConfig,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -198,6 +367,7 @@ pub enum SupervisorConfigError {
 #[cfg(test)]
 mod supervisor_config_tests {
     use super::*;
+    use crate::safety::Guard;
 
     const PARSE_STRING: &str = r#"
                 {
@@ -255,4 +425,95 @@ mod supervisor_config_tests {
         let config = SupervisorConfig::from(config);
         assert!(!config.nats.server.debug);
     }
+
+    #[test]
+    fn test_restart_default() {
+        let config: ParseSupervisorConfig = serde_json::from_str(PARSE_STRING).unwrap();
+        let config = SupervisorConfig::from(config);
+        assert_eq!(
+            config.restart.policy(&ClientId::from("mash")),
+            RestartPolicy::Never
+        );
+    }
+
+    #[test]
+    fn test_session_default() {
+        let config: ParseSupervisorConfig = serde_json::from_str(PARSE_STRING).unwrap();
+        let config = SupervisorConfig::from(config);
+        assert!(config.session.state_file.is_none());
+        assert!(!config.session.resume_on_start);
+    }
+
+    #[test]
+    fn test_validate_guards() {
+        let mut config = SupervisorConfig::dummy();
+        config.safety.guards = vec![Guard {
+            actor_id: ClientId::from("dummy_actor"),
+            sensor_id: ClientId::from("dummy_sensor"),
+            max_temp: Some(105.0),
+            max_staleness_ms: None,
+        }];
+        assert!(config.validate_guards().is_ok());
+        config.safety.guards[0].sensor_id = ClientId::from("kettle_temp");
+        assert!(config.validate_guards().is_err());
+        config.safety.guards[0].actor_id = ClientId::from("heater");
+        assert!(config.validate_guards().is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_timeout_default() {
+        let config: ParseSupervisorConfig = serde_json::from_str(PARSE_STRING).unwrap();
+        assert_eq!(config.general.heartbeat_timeout_ms, 10_000);
+    }
+
+    #[test]
+    fn test_restart_parse() {
+        let restart: RestartConfig = serde_json::from_str(
+            r#"
+            {
+              "default": "always",
+              "clients": {
+                "mash": {"on_failure": {"initial_backoff_ms": 500, "max_backoff_ms": 10000}}
+              }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            restart.policy(&ClientId::from("boil")),
+            RestartPolicy::Always
+        );
+        assert_eq!(
+            restart.policy(&ClientId::from("mash")),
+            RestartPolicy::OnFailure {
+                initial_backoff_ms: 500,
+                max_backoff_ms: 10000
+            }
+        );
+    }
+
+    #[test]
+    fn test_restart_backoff() {
+        let policy = RestartPolicy::OnFailure {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 3000,
+        };
+        assert_eq!(policy.restart_delay(false, 0), None);
+        assert_eq!(policy.restart_delay(true, 0), Some(500));
+        assert_eq!(policy.restart_delay(true, 2), Some(2000));
+        assert_eq!(policy.restart_delay(true, 3), Some(3000));
+        assert_eq!(policy.restart_delay(true, 100), Some(3000));
+        assert_eq!(RestartPolicy::Never.restart_delay(true, 0), None);
+        assert_eq!(
+            RestartPolicy::Always.restart_delay(false, 4),
+            Some(MIN_RESTART_DELAY_MS)
+        );
+        let no_backoff = RestartPolicy::OnFailure {
+            initial_backoff_ms: 0,
+            max_backoff_ms: 0,
+        };
+        assert_eq!(
+            no_backoff.restart_delay(true, 0),
+            Some(MIN_RESTART_DELAY_MS)
+        );
+    }
 }
@@ -6,17 +6,23 @@
 /// The supervisor is responsible for starting and monitoring all our basic clients, like sensors, actors and controllers.
 /// Via pub-sub messages we can also shut-down and start new clients during the brewing process.
 use self::config::SupervisorConfigError;
-use crate::actor::{ActorClient, ActorConfig, ActorError};
+use self::monitor::{ClientHealth, PendingRestart};
+use self::registry::RemoteClients;
+use self::session::SessionError;
+use crate::actor::{pub_sub::actor_turn_off_subject, ActorClient, ActorConfig, ActorError};
 use crate::control::{
-    pub_sub::ControllerPubMsg, ControllerClient, ControllerConfig, ControllerError,
+    pub_sub::{ControllerPubMsg, ControllerSubMsg},
+    Control, ControllerClient, ControllerConfig, ControllerError, Handover, State,
 };
 use crate::data_logger::DataLogger;
 use crate::logger::{debug, error, info, Log};
+use crate::program::{ProgramClient, ProgramConfig, ProgramError};
 use crate::pub_sub::{
     nats_client::{decode_nats_data, NatsClient, NatsClientConfig},
     ClientId, ClientState, PubSubClient, PubSubError, PubSubMsg,
 };
-use crate::sensor::{SensorClient, SensorConfig, SensorError};
+use crate::safety::{SafetyClient, SafetyError};
+use crate::sensor::{ds18b20, SensorClient, SensorConfig, SensorError};
 use crate::supervisor::pub_sub::{SupervisorPubMsg, SupervisorSubMsg};
 use crate::time::TimeStamp;
 use nats::Message;
@@ -25,10 +31,19 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::thread;
+use std::time::Duration;
 use thiserror::Error;
 
 pub mod config;
+pub mod monitor;
 pub mod pub_sub;
+pub mod registry;
+pub mod reload;
+pub mod session;
+pub mod shutdown;
+
+/// How long the supervisor waits for a client to reply to a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// BryggIO supervisor client
 ///
@@ -39,6 +54,9 @@ pub struct Supervisor {
     client: NatsClient,
     config: config::SupervisorConfig,
     active_clients: ActiveClients,
+    pending_restarts: Vec<PendingRestart>,
+    /// Sensor discovery running on its own thread, see [`Supervisor::reply_discovered_sensors`].
+    discovery: Option<thread::JoinHandle<Result<(), PubSubError>>>,
 }
 
 impl Supervisor {
@@ -56,10 +74,15 @@ impl Supervisor {
             client,
             config: config.clone(),
             active_clients: ActiveClients::new(),
+            pending_restarts: Vec::new(),
+            discovery: None,
         };
 
         supervisor.add_logger(&config)?;
         supervisor.add_data_logger(&config)?;
+        if !config.safety.guards.is_empty() {
+            supervisor.add_safety(&config)?;
+        }
 
         for sensor_config in config.hardware.sensors {
             supervisor.add_sensor(sensor_config, &nats_config)?;
@@ -69,6 +92,21 @@ impl Supervisor {
             supervisor.add_actor(actor_config, &nats_config)?;
         }
 
+        if config.session.resume_on_start {
+            match supervisor.resume_session() {
+                Ok(report) => info(
+                    &supervisor,
+                    format!("Resumed session: {:?}", report),
+                    "supervisor",
+                ),
+                Err(err) => error(
+                    &supervisor,
+                    format!("Could not resume session: {}", err),
+                    "supervisor",
+                ),
+            }
+        }
+
         info(&supervisor, String::from("Supervisor ready"), "supervisor");
         Ok(supervisor)
     }
@@ -80,15 +118,82 @@ impl Supervisor {
     ) -> Result<ClientState, SupervisorError> {
         match cmd {
             SupervisorSubMsg::StartController { contr_data } => {
-                self.start_controller(contr_data.config, contr_data.new_target, full_msg)?;
+                let res = self.start_controller(contr_data.config, contr_data.new_target, full_msg);
+                self.save_session();
+                res?;
                 Ok(ClientState::Active)
             }
             SupervisorSubMsg::StopController { contr_id } => {
-                self.stop_controller(&contr_id, full_msg)?;
+                let res = self.stop_controller(&contr_id, full_msg);
+                self.save_session();
+                res?;
                 Ok(ClientState::Active)
             }
             SupervisorSubMsg::SwitchController { contr_data } => {
-                self.switch_controller(contr_data.config, contr_data.new_target, full_msg)?;
+                let res =
+                    self.switch_controller(contr_data.config, contr_data.new_target, full_msg);
+                self.save_session();
+                res?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::ResumeSession => {
+                self.reply_resume_session(full_msg)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::ReloadConfig => {
+                self.reply_reload_config(full_msg)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::StartProgram { program_config } => {
+                let id = program_config.program_id.clone();
+                let res = self.start_program(program_config);
+                let ok_msg = format!("Program '{}' started", id);
+                self.reply_result(full_msg, "start program", &ok_msg, res)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::StopProgram { program_id } => {
+                let res = self.stop_program(&program_id);
+                let ok_msg = format!("Program '{}' stopped", program_id);
+                self.reply_result(full_msg, "stop program", &ok_msg, res)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::RegisterRemote { registration } => {
+                self.register_remote(registration, full_msg)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::AddSensor { sensor_config } => {
+                let id = sensor_config.id.clone();
+                let nats_config = NatsClientConfig::from(self.config.nats.server.clone());
+                let res = self.add_sensor(sensor_config, &nats_config);
+                self.reply_result(
+                    full_msg,
+                    "add sensor",
+                    &format!("Sensor '{}' added", id),
+                    res,
+                )?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::RemoveSensor { sensor_id } => {
+                let res = self.remove_sensor(&sensor_id);
+                let ok_msg = format!("Sensor '{}' removed", sensor_id);
+                self.reply_result(full_msg, "remove sensor", &ok_msg, res)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::AddActor { actor_config } => {
+                let id = actor_config.id.clone();
+                let nats_config = NatsClientConfig::from(self.config.nats.server.clone());
+                let res = self.add_actor(actor_config, &nats_config);
+                self.reply_result(full_msg, "add actor", &format!("Actor '{}' added", id), res)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::RemoveActor { actor_id } => {
+                let res = self.remove_actor(&actor_id);
+                let ok_msg = format!("Actor '{}' removed", actor_id);
+                self.reply_result(full_msg, "remove actor", &ok_msg, res)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::DiscoverSensors => {
+                self.reply_discovered_sensors(full_msg)?;
                 Ok(ClientState::Active)
             }
             SupervisorSubMsg::ListActiveClients => {
@@ -108,7 +213,18 @@ impl Supervisor {
                 };
                 Ok(ClientState::Active)
             }
-            SupervisorSubMsg::Stop => Ok(ClientState::Inactive),
+            SupervisorSubMsg::Stop => {
+                // The clients are already stopped, so a failed reply must not keep the supervisor
+                // running.
+                if let Err(err) = self.reply_shutdown(full_msg) {
+                    error(
+                        self,
+                        format!("Failed replying with shutdown report: {}", err),
+                        "supervisor",
+                    );
+                }
+                Ok(ClientState::Inactive)
+            }
         }
     }
 
@@ -119,7 +235,7 @@ impl Supervisor {
         msg: &Message,
     ) -> Result<(), SupervisorError> {
         let id = contr_config.controller_id.clone();
-        let start_res = self.common_start_controller(contr_config, target);
+        let start_res = self.common_start_controller(contr_config, target, None);
         match start_res {
             Ok(()) => Ok(msg
                 .respond(format!("Controller '{}' started", id,))
@@ -140,43 +256,61 @@ impl Supervisor {
         }
     }
 
+    /// Start a controller, continuing from the state of a previous controller if handed over.
     fn common_start_controller(
         &mut self,
         contr_config: ControllerConfig,
         target: f32,
+        handover: Option<Handover>,
+    ) -> Result<(), SupervisorError> {
+        self.ensure_controller_clients(&contr_config)?;
+        let mut controller = contr_config.get_controller(target)?;
+        if let Some(handover) = handover {
+            controller.take_over(&handover);
+        }
+        self.spawn_controller(contr_config, controller, target)
+    }
+
+    /// Check that every sensor and actor of a controller is available.
+    fn ensure_controller_clients(
+        &self,
+        contr_config: &ControllerConfig,
     ) -> Result<(), SupervisorError> {
-        // TODO: Disabled checks pga SensorBox
         let missing_ids = contr_config
             .client_ids()
-            .map(|id| (id, self.client_is_active(id)))
+            .map(|id| (id, self.client_is_available(id)))
             .filter(|(_, exists)| !*exists)
             .map(|(id, _)| String::from(id.clone()))
             .reduce(|acc, id| format!("{}, {}", acc, id));
 
-        if let Some(ids) = missing_ids {
-            return Err(SupervisorError::Missing(ClientId(ids)));
+        match missing_ids {
+            Some(ids) => Err(SupervisorError::Missing(ClientId(ids))),
+            None => Ok(()),
         }
+    }
 
+    fn spawn_controller(
+        &mut self,
+        contr_config: ControllerConfig,
+        controller: Box<dyn Control>,
+        target: f32,
+    ) -> Result<(), SupervisorError> {
         let id = contr_config.controller_id.clone();
-        match self.active_clients.controllers.get(&id) {
-            Some(_) => Err(SupervisorError::AlreadyActive(id.clone())),
-            None => {
-                let controller_client = ControllerClient::new(
-                    id.clone(),
-                    contr_config.actor_id.clone(),
-                    contr_config.sensor_id.clone(),
-                    contr_config.get_controller(target)?,
-                    &NatsClientConfig::from(self.config.nats.server.clone()),
-                    contr_config.type_.clone(),
-                );
-                let control_handle =
-                    thread::spawn(|| controller_client.client_loop().map_err(|err| err.into()));
-                self.active_clients
-                    .controllers
-                    .insert(id.clone(), (control_handle, contr_config));
-                Ok(())
-            }
+        if self.active_clients.controllers.contains_key(&id) {
+            return Err(SupervisorError::AlreadyActive(id));
         }
+        let controller_client = ControllerClient::new(
+            &contr_config,
+            controller,
+            &NatsClientConfig::from(self.config.nats.server.clone()),
+        );
+        let control_handle =
+            thread::spawn(|| controller_client.client_loop().map_err(|err| err.into()));
+        self.active_clients
+            .controllers
+            .insert(id.clone(), (control_handle, contr_config));
+        self.active_clients.controller_targets.insert(id, target);
+        Ok(())
     }
 
     fn stop_controller(
@@ -224,24 +358,72 @@ impl Supervisor {
             "supervisor",
         );
         let contr_id = &config.controller_id;
-        if let Err(err) = self.common_stop_controller(contr_id) {
+        // The new controller is checked before the running one is stopped, so that an invalid
+        // config leaves the running controller as is.
+        let checked = self.ensure_controller_clients(&config).and_then(|()| {
+            config
+                .get_controller(new_target)
+                .map_err(SupervisorError::from)
+        });
+        let mut controller = match checked {
+            Ok(controller) => controller,
+            Err(err) => {
+                msg.respond(format!(
+                    "Invalid controller '{}', keeping the running one: {}",
+                    contr_id, err
+                ))
+                .map_err(|err| PubSubError::Reply {
+                    task: "check contr. when switching contr.",
+                    msg: msg.clone(),
+                    source: err,
+                })?;
+                return Err(err);
+            }
+        };
+        let handover = match self.hand_over_controller(&config) {
+            Ok(handover) => handover,
+            Err(err) => {
+                msg.respond(format!(
+                    "Failed stopping controller '{}': {}",
+                    contr_id, err
+                ))
+                .map_err(|err| PubSubError::Reply {
+                    task: "stop contr. when switching contr.",
+                    msg: msg.clone(),
+                    source: err,
+                })?;
+                return Err(err);
+            }
+        };
+        let handed_over = handover.is_some();
+        if let Some(handover) = handover {
+            controller.take_over(&handover);
+        }
+        if let Err(err) = self.spawn_controller(config.clone(), controller, new_target) {
+            // Nothing drives the actors that were left on for the new controller.
+            if handed_over {
+                self.turn_off_actors(&config);
+            }
             msg.respond(format!(
-                "Failed stopping controller '{}': {}",
+                "Failed starting controller '{}': {}",
                 contr_id, err
             ))
             .map_err(|err| PubSubError::Reply {
-                task: "stop contr. when switching contr.",
+                task: "start contr. when switching contr.",
                 msg: msg.clone(),
                 source: err,
             })?;
-            return Err(SupervisorError::Missing(contr_id.clone()));
-        };
-        self.common_start_controller(config.clone(), new_target)?;
+            return Err(err);
+        }
         let status: PubSubMsg = ControllerPubMsg::Status {
             id: contr_id.clone(),
             timestamp: TimeStamp::now(),
             target: new_target,
+            effective_target: Some(new_target),
+            state: State::Active,
             type_: config.type_,
+            degraded: false,
+            loops: Vec::new(),
         }
         .into();
         Ok(msg
@@ -253,10 +435,85 @@ impl Supervisor {
             })?)
     }
 
+    /// Stop a controller so that a new one can continue from its state.
+    ///
+    /// The actors are only left on if the new controller drives the same ones,
+    /// otherwise the controller is stopped as usual and there is nothing to hand over.
+    fn hand_over_controller(
+        &mut self,
+        config: &ControllerConfig,
+    ) -> Result<Option<Handover>, SupervisorError> {
+        let contr_id = &config.controller_id;
+        let same_actors = match self.active_clients.controllers.get(contr_id) {
+            Some((_, old_config)) => old_config.actor_ids().eq(config.actor_ids()),
+            None => return Err(SupervisorError::Missing(contr_id.clone())),
+        };
+        if !same_actors {
+            return self.common_stop_controller(contr_id).map(|()| None);
+        }
+        let reply = self
+            .client
+            .request_timeout(
+                &ControllerSubMsg::handover_subject(contr_id),
+                &PubSubMsg::empty(),
+                REQUEST_TIMEOUT,
+            )
+            .map_err(|_| SupervisorError::Timeout(contr_id.clone()))?;
+        self.join_client(contr_id)?;
+        let handover: Handover = decode_nats_data(&reply.data).map_err(PubSubError::from)?;
+        Ok(Some(handover))
+    }
+
+    /// Turn off the actors of a controller, failures are logged.
+    fn turn_off_actors(&self, contr_config: &ControllerConfig) {
+        for actor_id in contr_config.actor_ids() {
+            if let Err(err) = self.client.request_timeout(
+                &actor_turn_off_subject(actor_id),
+                &PubSubMsg::empty(),
+                REQUEST_TIMEOUT,
+            ) {
+                error(
+                    self,
+                    format!("Failed turning off actor '{}': {}", actor_id, err),
+                    "supervisor",
+                );
+            }
+        }
+    }
+
+    /// Start a brew program driving already active controllers.
+    fn start_program(&mut self, config: ProgramConfig) -> Result<(), SupervisorError> {
+        config.validate()?;
+        let id = config.program_id.clone();
+        if self.client_is_active(&id) {
+            return Err(SupervisorError::AlreadyActive(id));
+        }
+        if let Some(contr_id) = config
+            .controller_ids()
+            .find(|contr_id| !self.active_clients.controllers.contains_key(contr_id))
+        {
+            return Err(SupervisorError::Missing(contr_id.clone()));
+        }
+        let program = ProgramClient::try_new(
+            config.clone(),
+            &NatsClientConfig::from(self.config.nats.server.clone()),
+        )?;
+        let handle = thread::spawn(|| program.client_loop().map_err(|err| err.into()));
+        self.active_clients.programs.insert(id, (handle, config));
+        Ok(())
+    }
+
+    fn stop_program(&mut self, id: &ClientId) -> Result<(), SupervisorError> {
+        if !self.active_clients.programs.contains_key(id) {
+            return Err(SupervisorError::Missing(id.clone()));
+        }
+        self.kill_client::<()>(id)
+    }
+
     fn reply_active_clients(&self, msg: &Message) -> Result<(), PubSubError> {
         debug(self, String::from("Listing active clients"), "supervisor");
-        let clients = PubSubMsg::from(SupervisorPubMsg::ActiveClients(ActiveClientsList::from(
-            &self.active_clients,
+        let clients = PubSubMsg::from(SupervisorPubMsg::ActiveClients(Box::new(
+            ActiveClientsList::from(&self.active_clients),
         )));
         // println!("Clients within function {}", clients.to_string());
         // println!("Clients within function (struct) {:?}", self.active_clients);
@@ -268,23 +525,92 @@ impl Supervisor {
             })
     }
 
+    /// Reply with the DS18B20 sensors on the 1-wire bus and their current readings.
+    ///
+    /// Discovery runs on a worker thread, since every sensor found is read, which takes up to a
+    /// second per sensor, and must not keep the supervisor from handling other commands.
+    fn reply_discovered_sensors(&mut self, msg: &Message) -> Result<(), PubSubError> {
+        self.finish_discovery();
+        if self.discovery.is_some() {
+            return msg
+                .respond("Sensor discovery already running")
+                .map_err(|err| PubSubError::Reply {
+                    task: "discover sensors",
+                    msg: msg.clone(),
+                    source: err,
+                });
+        }
+        debug(self, String::from("Discovering sensors"), "supervisor");
+        let one_wire_dir = self.config.hardware.one_wire_dir.clone();
+        let msg = msg.clone();
+        self.discovery = Some(thread::spawn(move || {
+            let response = match ds18b20::discover(&one_wire_dir) {
+                Ok(sensors) => {
+                    PubSubMsg::from(SupervisorPubMsg::DiscoveredSensors(sensors)).to_string()
+                }
+                Err(err) => format!("Failed to discover sensors: {}", err),
+            };
+            msg.respond(response).map_err(|err| PubSubError::Reply {
+                task: "discover sensors",
+                msg: msg.clone(),
+                source: err,
+            })
+        }));
+        Ok(())
+    }
+
+    /// Join a finished sensor discovery, logging a failed reply.
+    pub(crate) fn finish_discovery(&mut self) {
+        if !self
+            .discovery
+            .as_ref()
+            .is_some_and(|handle| handle.is_finished())
+        {
+            return;
+        }
+        let res = match self.discovery.take().map(|handle| handle.join()) {
+            Some(Ok(res)) => res.map_err(|err| err.to_string()),
+            Some(Err(_)) => Err(String::from("discovery thread panicked")),
+            None => Ok(()),
+        };
+        if let Err(err) = res {
+            error(
+                self,
+                format!("Sensor discovery failed: {}", err),
+                "supervisor",
+            );
+        }
+    }
+
+    /// Ask a client to exit and wait for its thread to finish.
     fn kill_client<T: DeserializeOwned>(&mut self, id: &ClientId) -> Result<T, SupervisorError> {
-        // TODO: Non-generic hack.
-        let (handle, _config) = match self.active_clients.controllers.remove(id) {
-            Some(contr) => Ok(contr),
-            None => Err(SupervisorError::Missing(id.clone())),
-        }?;
+        let report = self.request_kill(id)?;
+        self.join_client(id)?;
+        Ok(decode_nats_data::<T>(&report.data).map_err(PubSubError::from)?)
+    }
+
+    fn request_kill(&self, id: &ClientId) -> Result<Message, SupervisorError> {
+        if !self.active_clients.contains_id(id) {
+            return Err(SupervisorError::Missing(id.clone()));
+        }
         let msg = SupervisorPubMsg::KillClient {
             client_id: id.clone(),
         };
-        let report = self.client.request(&msg.subject(), &msg.into())?;
+        // A client blocked e.g., in a sensor read must not block the supervisor.
+        self.client
+            .request_timeout(&msg.subject(), &msg.into(), REQUEST_TIMEOUT)
+            .map_err(|_| SupervisorError::Timeout(id.clone()))
+    }
+
+    fn join_client(&mut self, id: &ClientId) -> Result<(), SupervisorError> {
+        let handle = self
+            .active_clients
+            .remove(id)
+            .ok_or_else(|| SupervisorError::Missing(id.clone()))?;
         match handle.join() {
-            Ok(_) => {}
-            Err(_) => {
-                return Err(SupervisorError::ThreadJoin(id.clone()));
-            }
-        };
-        Ok(decode_nats_data::<T>(&report.data).map_err(PubSubError::from)?)
+            Ok(_) => Ok(()),
+            Err(_) => Err(SupervisorError::ThreadJoin(id.clone())),
+        }
     }
 
     fn add_logger(&mut self, config: &config::SupervisorConfig) -> Result<(), SupervisorError> {
@@ -310,21 +636,32 @@ impl Supervisor {
         self.add_misc_client(id, log_handle)
     }
 
+    fn add_safety(&mut self, config: &config::SupervisorConfig) -> Result<(), SupervisorError> {
+        let id = ClientId::from("safety");
+        let mut safety = SafetyClient::try_new(
+            id.clone(),
+            config.safety.clone(),
+            &NatsClientConfig::from(config.nats.server.clone()),
+        )?;
+        safety.restore_latches()?;
+        let handle = thread::spawn(|| safety.client_loop().map_err(|err| err.into()));
+        self.add_misc_client(id, handle)
+    }
+
     fn add_sensor(
         &mut self,
         sensor_config: SensorConfig,
         config: &NatsClientConfig,
     ) -> Result<(), SupervisorError> {
         let id = &sensor_config.id;
-        match self.active_clients.sensors.get(id) {
-            Some(_) => Err(SupervisorError::AlreadyActive(id.clone())),
-            None => {
-                let sensor = SensorClient::new(
-                    sensor_config.id.clone(),
-                    sensor_config.create_sensor()?,
-                    config,
-                );
+        // An id registered by a remote node must not be shadowed by a local client.
+        match self.client_is_available(id) {
+            true => Err(SupervisorError::AlreadyActive(id.clone())),
+            false => {
+                let sensor = sensor_config.create_sensor(&self.config.hardware.one_wire_dir)?;
+                let sensor = SensorClient::new(&sensor_config, sensor, config);
                 let handle = thread::spawn(|| sensor.client_loop().map_err(|err| err.into()));
+                self.config.hardware.track_sensor(sensor_config.clone());
                 self.active_clients
                     .sensors
                     .insert(id.clone(), (handle, sensor_config));
@@ -339,11 +676,12 @@ impl Supervisor {
         config: &NatsClientConfig,
     ) -> Result<(), SupervisorError> {
         let id = &actor_config.id;
-        match self.active_clients.actors.get(id) {
-            Some(_) => Err(SupervisorError::AlreadyActive(id.clone())),
-            None => {
+        match self.client_is_available(id) {
+            true => Err(SupervisorError::AlreadyActive(id.clone())),
+            false => {
                 let actor = ActorClient::new(id.clone(), actor_config.get_actor()?, config);
                 let handle = thread::spawn(|| actor.client_loop().map_err(|err| err.into()));
+                self.config.hardware.track_actor(actor_config.clone());
                 self.active_clients
                     .actors
                     .insert(id.clone(), (handle, actor_config));
@@ -352,10 +690,88 @@ impl Supervisor {
         }
     }
 
+    fn remove_sensor(&mut self, id: &ClientId) -> Result<(), SupervisorError> {
+        self.ensure_unused(id)?;
+        if self.cancel_restart(id) {
+            self.config.hardware.forget(id);
+            return Ok(());
+        }
+        if !self.active_clients.sensors.contains_key(id) {
+            return Err(SupervisorError::Missing(id.clone()));
+        }
+        self.kill_client::<()>(id)?;
+        self.active_clients.health.remove(id);
+        self.config.hardware.forget(id);
+        Ok(())
+    }
+
+    /// Turn off and remove an actor.
+    ///
+    /// The actor client only exits once the actor is confirmed off;
+    /// otherwise it is kept as an active client.
+    fn remove_actor(&mut self, id: &ClientId) -> Result<(), SupervisorError> {
+        self.ensure_unused(id)?;
+        if self.cancel_restart(id) {
+            self.config.hardware.forget(id);
+            return Ok(());
+        }
+        if !self.active_clients.actors.contains_key(id) {
+            return Err(SupervisorError::Missing(id.clone()));
+        }
+        let report = self.request_kill(id)?;
+        decode_nats_data::<Result<(), String>>(&report.data)
+            .map_err(PubSubError::from)?
+            .map_err(|err| SupervisorError::Actor(ActorError::Generic(err)))?;
+        self.join_client(id)?;
+        self.active_clients.health.remove(id);
+        self.config.hardware.forget(id);
+        Ok(())
+    }
+
+    /// Check that no active controller depends on the client.
+    fn ensure_unused(&self, id: &ClientId) -> Result<(), SupervisorError> {
+        match self
+            .active_clients
+            .controllers
+            .values()
+            .find(|(_, config)| config.client_ids().any(|client_id| client_id == id))
+        {
+            Some((_, config)) => Err(SupervisorError::InUse(
+                id.clone(),
+                config.controller_id.clone(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn reply_result(
+        &self,
+        msg: &Message,
+        task: &'static str,
+        ok_msg: &str,
+        res: Result<(), SupervisorError>,
+    ) -> Result<(), SupervisorError> {
+        let response = match &res {
+            Ok(()) => String::from(ok_msg),
+            Err(err) => format!("Failed to {}: {}", task, err),
+        };
+        msg.respond(response).map_err(|err| PubSubError::Reply {
+            task,
+            msg: msg.clone(),
+            source: err,
+        })?;
+        res
+    }
+
     fn client_is_active(&self, id: &ClientId) -> bool {
         self.active_clients.contains_id(id)
     }
 
+    /// Check that a client is either active locally or registered by a remote node.
+    fn client_is_available(&self, id: &ClientId) -> bool {
+        self.client_is_active(id) || self.active_clients.remote.contains_id(id, TimeStamp::now())
+    }
+
     fn add_misc_client(
         &mut self,
         new_client: ClientId,
@@ -390,7 +806,14 @@ struct ActiveClients {
     sensors: HashMap<ClientId, (Handle, SensorConfig)>,
     actors: HashMap<ClientId, (Handle, ActorConfig)>,
     controllers: HashMap<ClientId, (Handle, ControllerConfig)>,
+    programs: HashMap<ClientId, (Handle, ProgramConfig)>,
     misc: HashMap<ClientId, Handle>,
+    /// Target of each controller, used when restarting a controller.
+    controller_targets: HashMap<ClientId, f32>,
+    /// Restart history, kept also for clients that have exited.
+    health: HashMap<ClientId, ClientHealth>,
+    /// Sensors and actors registered by remote nodes.
+    remote: RemoteClients,
 }
 
 impl ActiveClients {
@@ -399,7 +822,11 @@ impl ActiveClients {
             sensors: HashMap::new(),
             actors: HashMap::new(),
             controllers: HashMap::new(),
+            programs: HashMap::new(),
             misc: HashMap::new(),
+            controller_targets: HashMap::new(),
+            health: HashMap::new(),
+            remote: RemoteClients::default(),
         }
     }
 
@@ -407,6 +834,29 @@ impl ActiveClients {
         self.sensors.contains_key(id)
             || self.actors.contains_key(id)
             || self.controllers.contains_key(id)
+            || self.programs.contains_key(id)
+    }
+
+    /// Remove a sensor, actor, controller or program, returning its thread handle.
+    fn remove(&mut self, id: &ClientId) -> Option<Handle> {
+        self.controller_targets.remove(id);
+        self.sensors
+            .remove(id)
+            .map(|(handle, _)| handle)
+            .or_else(|| self.actors.remove(id).map(|(handle, _)| handle))
+            .or_else(|| self.controllers.remove(id).map(|(handle, _)| handle))
+            .or_else(|| self.programs.remove(id).map(|(handle, _)| handle))
+    }
+
+    /// Thread handle of any client, including the misc clients.
+    fn handle(&self, id: &ClientId) -> Option<&Handle> {
+        self.sensors
+            .get(id)
+            .map(|(handle, _)| handle)
+            .or_else(|| self.actors.get(id).map(|(handle, _)| handle))
+            .or_else(|| self.controllers.get(id).map(|(handle, _)| handle))
+            .or_else(|| self.programs.get(id).map(|(handle, _)| handle))
+            .or_else(|| self.misc.get(id))
     }
 }
 
@@ -415,7 +865,14 @@ pub struct ActiveClientsList {
     sensors: HashMap<ClientId, SensorConfig>,
     actors: HashMap<ClientId, ActorConfig>,
     controllers: HashMap<ClientId, ControllerConfig>,
+    #[serde(default)]
+    controller_targets: HashMap<ClientId, f32>,
+    #[serde(default)]
+    programs: HashMap<ClientId, ProgramConfig>,
     misc: Vec<ClientId>,
+    health: HashMap<ClientId, ClientHealth>,
+    #[serde(default)]
+    remote: RemoteClients,
 }
 
 impl ActiveClientsList {
@@ -444,7 +901,15 @@ impl From<&ActiveClients> for ActiveClientsList {
                 .iter()
                 .map(|(id, (_, config))| (id.clone(), config.clone()))
                 .collect(),
+            controller_targets: clients.controller_targets.clone(),
+            programs: clients
+                .programs
+                .iter()
+                .map(|(id, (_, config))| (id.clone(), config.clone()))
+                .collect(),
             misc: clients.misc.keys().cloned().collect(),
+            health: clients.health.clone(),
+            remote: clients.remote.clone(),
         }
     }
 }
@@ -459,8 +924,12 @@ pub enum SupervisorError {
     Missing(ClientId),
     #[error("'{0}' is already an active client")]
     AlreadyActive(ClientId),
+    #[error("'{0}' is used by controller '{1}'")]
+    InUse(ClientId, ClientId),
     #[error("Control error: {0}")]
     Controller(#[from] ControllerError),
+    #[error("Program error: {0}")]
+    Program(#[from] ProgramError),
     #[error("Sensor error: {0}")]
     Sensor(#[from] SensorError),
     #[error("Actor error: {0}")]
@@ -469,4 +938,12 @@ pub enum SupervisorError {
     PubSub(#[from] PubSubError),
     #[error("Could not join thread with client id {0}")]
     ThreadJoin(ClientId),
+    #[error("Client '{0}' panicked: {1}")]
+    Panic(ClientId, String),
+    #[error("Timed out waiting for client '{0}' to stop")]
+    Timeout(ClientId),
+    #[error("Session error: {0}")]
+    Session(#[from] SessionError),
+    #[error("Safety error: {0}")]
+    Safety(#[from] SafetyError),
 }
@@ -0,0 +1,389 @@
+//! Monitoring of client threads
+//!
+//! Every client started by the supervisor runs in its own thread.
+//! The supervisor periodically checks for threads that have exited,
+//! logs the reason and restarts the client according to its [`RestartPolicy`].
+//! Clients that are running but whose heartbeats are overdue are flagged as such.
+use super::{ActiveClients, Handle, Supervisor, SupervisorError};
+use crate::actor::ActorConfig;
+use crate::control::ControllerConfig;
+use crate::logger::{error, info, warning};
+use crate::pub_sub::heartbeat::HeartbeatMsg;
+use crate::pub_sub::{
+    nats_client::{decode_nats_data, NatsClientConfig},
+    ClientId,
+};
+use crate::sensor::SensorConfig;
+use crate::supervisor::config::RestartPolicy;
+use crate::time::TimeStamp;
+use nats::Message;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+
+/// Restart history and liveness of a client.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ClientHealth {
+    /// Successful restarts of the client.
+    pub restart_count: u32,
+    /// Restarts tried, including those that failed to start the client, drives the backoff.
+    #[serde(default)]
+    pub restart_attempts: u32,
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub liveness: Liveness,
+    /// Most recent heartbeat received from the client.
+    #[serde(default)]
+    pub heartbeat: Option<HeartbeatMsg>,
+}
+
+/// Liveness of a client, based on its heartbeats.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Liveness {
+    /// No heartbeat received yet.
+    #[default]
+    #[serde(rename = "unknown")]
+    Unknown,
+    #[serde(rename = "alive")]
+    Alive,
+    /// The client thread is running, but its last heartbeat is older than the timeout.
+    #[serde(rename = "overdue")]
+    Overdue,
+}
+
+impl ClientHealth {
+    fn current_liveness(&self, now: TimeStamp, timeout: TimeStamp) -> Liveness {
+        match &self.heartbeat {
+            Some(heartbeat) if heartbeat.timestamp + timeout < now => Liveness::Overdue,
+            Some(_) => Liveness::Alive,
+            None => Liveness::Unknown,
+        }
+    }
+
+    /// Delay before the next restart, counted as an attempt whether or not the client starts.
+    fn schedule_restart(&mut self, policy: &RestartPolicy, failed: bool) -> Option<u64> {
+        let delay = policy.restart_delay(failed, self.restart_attempts);
+        if delay.is_some() {
+            self.restart_attempts += 1;
+        }
+        delay
+    }
+}
+
+/// A client whose thread has exited, with what is needed to start it again.
+#[derive(Debug, Clone)]
+pub(crate) enum StoppedClient {
+    Sensor(SensorConfig),
+    Actor(ActorConfig),
+    Controller(ControllerConfig, f32),
+    /// Programs are never restarted, a restarted program would start over from its first step.
+    Program(ClientId),
+    Misc(ClientId),
+}
+
+impl StoppedClient {
+    fn id(&self) -> ClientId {
+        match self {
+            StoppedClient::Sensor(config) => config.id.clone(),
+            StoppedClient::Actor(config) => config.id.clone(),
+            StoppedClient::Controller(config, _) => config.controller_id.clone(),
+            StoppedClient::Program(id) | StoppedClient::Misc(id) => id.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PendingRestart {
+    client: StoppedClient,
+    due: TimeStamp,
+}
+
+impl Supervisor {
+    /// Handle exited clients and restart the ones that are due.
+    pub(crate) fn monitor_clients(&mut self) {
+        for (client, res) in self.active_clients.take_finished() {
+            self.handle_exit(client, res);
+        }
+        self.restart_due_clients();
+        self.check_heartbeats();
+        self.prune_remote_clients();
+        self.finish_discovery();
+    }
+
+    /// Store a heartbeat published by a client.
+    pub(crate) fn track_heartbeat(&mut self, msg: &Message) {
+        let heartbeat = match decode_nats_data::<HeartbeatMsg>(&msg.data) {
+            Ok(heartbeat) if self.is_running(&heartbeat.id) => heartbeat,
+            _ => return,
+        };
+        let id = heartbeat.id.clone();
+        let health = self.active_clients.health.entry(id.clone()).or_default();
+        let revived = health.liveness == Liveness::Overdue;
+        health.heartbeat = Some(heartbeat);
+        health.liveness = Liveness::Alive;
+        if revived {
+            info(
+                self,
+                format!("Client '{}' is alive again", id),
+                "supervisor",
+            );
+        }
+    }
+
+    /// Flag running clients whose heartbeat is overdue.
+    fn check_heartbeats(&mut self) {
+        let now = TimeStamp::now();
+        let timeout = TimeStamp(u128::from(self.config.general.heartbeat_timeout_ms));
+        let ids: Vec<ClientId> = self.active_clients.health.keys().cloned().collect();
+        for id in ids {
+            let running = self.is_running(&id);
+            if let Some(health) = self.active_clients.health.get_mut(&id) {
+                let liveness = match running {
+                    true => health.current_liveness(now, timeout),
+                    false => Liveness::Unknown,
+                };
+                let became_overdue =
+                    liveness == Liveness::Overdue && health.liveness != Liveness::Overdue;
+                health.liveness = liveness;
+                if became_overdue {
+                    warning(
+                        self,
+                        format!("Heartbeat from client '{}' is overdue", id),
+                        "supervisor",
+                    );
+                }
+            }
+        }
+    }
+
+    fn is_running(&self, id: &ClientId) -> bool {
+        self.active_clients.contains_id(id) || self.active_clients.misc.contains_key(id)
+    }
+
+    fn handle_exit(&mut self, client: StoppedClient, res: Result<(), SupervisorError>) {
+        let id = client.id();
+        let failed = res.is_err();
+        match res {
+            Ok(()) => info(self, format!("Client '{}' exited", id), "supervisor"),
+            Err(err) => {
+                error(
+                    self,
+                    format!("Client '{}' exited with error: {}", id, err),
+                    "supervisor",
+                );
+                self.active_clients
+                    .health
+                    .entry(id.clone())
+                    .or_default()
+                    .last_error = Some(err.to_string());
+            }
+        }
+
+        if let StoppedClient::Program(_) = client {
+            return;
+        }
+        let policy: RestartPolicy = self.config.restart.policy(&id);
+        let delay = self
+            .active_clients
+            .health
+            .entry(id.clone())
+            .or_default()
+            .schedule_restart(&policy, failed);
+        match delay {
+            Some(delay) => {
+                info(
+                    self,
+                    format!("Restarting client '{}' in {} ms", id, delay),
+                    "supervisor",
+                );
+                self.pending_restarts.push(PendingRestart {
+                    client,
+                    due: TimeStamp::now() + TimeStamp(u128::from(delay)),
+                });
+            }
+            // A controller that is gone for good must not be resumed with the session.
+            None if matches!(client, StoppedClient::Controller(..)) => self.save_session(),
+            None => {}
+        }
+    }
+
+    fn restart_due_clients(&mut self) {
+        let now = TimeStamp::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = self
+            .pending_restarts
+            .drain(..)
+            .partition(|pending| pending.due <= now);
+        self.pending_restarts = waiting;
+
+        for pending in due {
+            let id = pending.client.id();
+            match self.restart_client(&pending.client) {
+                Ok(()) => {
+                    self.active_clients
+                        .health
+                        .entry(id.clone())
+                        .or_default()
+                        .restart_count += 1;
+                    info(self, format!("Client '{}' restarted", id), "supervisor")
+                }
+                // Scheduled again, no sooner than the minimum restart delay.
+                Err(err) => self.handle_exit(pending.client, Err(err)),
+            }
+        }
+    }
+
+    /// Drop a scheduled restart, returns `true` if the client was awaiting one.
+    pub(crate) fn cancel_restart(&mut self, id: &ClientId) -> bool {
+        let pending_count = self.pending_restarts.len();
+        self.pending_restarts
+            .retain(|pending| &pending.client.id() != id);
+        let cancelled = self.pending_restarts.len() < pending_count;
+        if cancelled {
+            self.active_clients.health.remove(id);
+        }
+        cancelled
+    }
+
+    fn restart_client(&mut self, client: &StoppedClient) -> Result<(), SupervisorError> {
+        let nats_config = NatsClientConfig::from(self.config.nats.server.clone());
+        match client {
+            StoppedClient::Sensor(config) => self.add_sensor(config.clone(), &nats_config),
+            StoppedClient::Actor(config) => self.add_actor(config.clone(), &nats_config),
+            StoppedClient::Controller(config, target) => {
+                self.common_start_controller(config.clone(), *target, None)
+            }
+            StoppedClient::Program(id) => Err(SupervisorError::Missing(id.clone())),
+            StoppedClient::Misc(id) => {
+                let config = self.config.clone();
+                match id.as_ref() {
+                    "log" => self.add_logger(&config),
+                    "data_logger" => self.add_data_logger(&config),
+                    "safety" => self.add_safety(&config),
+                    _ => Err(SupervisorError::Missing(id.clone())),
+                }
+            }
+        }
+    }
+}
+
+impl ActiveClients {
+    /// Remove and join all client threads that have exited.
+    fn take_finished(&mut self) -> Vec<(StoppedClient, Result<(), SupervisorError>)> {
+        let mut finished: Vec<_> = take_finished(&mut self.sensors)
+            .into_iter()
+            .map(|(id, handle, config)| (StoppedClient::Sensor(config), join(&id, handle)))
+            .collect();
+        finished.extend(
+            take_finished(&mut self.actors)
+                .into_iter()
+                .map(|(id, handle, config)| (StoppedClient::Actor(config), join(&id, handle))),
+        );
+        for (id, handle, config) in take_finished(&mut self.controllers) {
+            let target = self.controller_targets.remove(&id).unwrap_or(0.0);
+            let res = join(&id, handle);
+            finished.push((StoppedClient::Controller(config, target), res));
+        }
+        for (id, handle, _) in take_finished(&mut self.programs) {
+            let res = join(&id, handle);
+            finished.push((StoppedClient::Program(id), res));
+        }
+        let misc_ids: Vec<ClientId> = self
+            .misc
+            .iter()
+            .filter(|(_, handle)| handle.is_finished())
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in misc_ids {
+            if let Some(handle) = self.misc.remove(&id) {
+                let res = join(&id, handle);
+                finished.push((StoppedClient::Misc(id), res));
+            }
+        }
+        finished
+    }
+}
+
+fn take_finished<C>(clients: &mut HashMap<ClientId, (Handle, C)>) -> Vec<(ClientId, Handle, C)> {
+    let ids: Vec<ClientId> = clients
+        .iter()
+        .filter(|(_, (handle, _))| handle.is_finished())
+        .map(|(id, _)| id.clone())
+        .collect();
+    ids.into_iter()
+        .filter_map(|id| {
+            clients
+                .remove(&id)
+                .map(|(handle, config)| (id, handle, config))
+        })
+        .collect()
+}
+
+pub(super) fn join(id: &ClientId, handle: Handle) -> Result<(), SupervisorError> {
+    match handle.join() {
+        Ok(res) => res,
+        Err(panic) => Err(SupervisorError::Panic(id.clone(), panic_msg(panic))),
+    }
+}
+
+fn panic_msg(panic: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        String::from(*msg)
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        String::from("Unknown panic")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pub_sub::ClientState;
+
+    #[test]
+    fn test_liveness() {
+        let timeout = TimeStamp(5000);
+        let mut health = ClientHealth::default();
+        assert_eq!(
+            health.current_liveness(TimeStamp(1000), timeout),
+            Liveness::Unknown
+        );
+        health.heartbeat = Some(HeartbeatMsg {
+            id: ClientId::from("mash"),
+            timestamp: TimeStamp(1000),
+            state: ClientState::Active,
+            uptime_ms: 0,
+            last_error: None,
+        });
+        assert_eq!(
+            health.current_liveness(TimeStamp(6000), timeout),
+            Liveness::Alive
+        );
+        assert_eq!(
+            health.current_liveness(TimeStamp(6001), timeout),
+            Liveness::Overdue
+        );
+    }
+
+    #[test]
+    fn test_backoff_on_failed_starts() {
+        let policy = RestartPolicy::OnFailure {
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 10000,
+        };
+        let mut health = ClientHealth::default();
+        // The client never starts, so only attempts are counted.
+        let delays: Vec<Option<u64>> = (0..5)
+            .map(|_| health.schedule_restart(&policy, true))
+            .collect();
+        assert_eq!(
+            delays,
+            vec![Some(1000), Some(2000), Some(4000), Some(8000), Some(10000)]
+        );
+        assert_eq!(health.restart_count, 0);
+        assert_eq!(health.restart_attempts, 5);
+        // A clean exit is not restarted and does not count.
+        assert_eq!(health.schedule_restart(&policy, false), None);
+        assert_eq!(health.restart_attempts, 5);
+    }
+}
@@ -1,8 +1,13 @@
-use crate::pub_sub::PubSubMsg;
+use crate::actor::ActorConfig;
+use crate::program::ProgramConfig;
+use crate::pub_sub::{heartbeat::HeartbeatMsg, PubSubMsg};
 use crate::pub_sub::{
     nats_client::decode_nats_data, ClientId, ClientState, PubSubClient, PubSubError, Subject,
 };
+use crate::sensor::{ds18b20::DiscoveredDs18b20, SensorCommand, SensorConfig};
+use crate::supervisor::registry::RemoteRegistration;
 use crate::supervisor::{ActiveClientsList, Supervisor};
+use crate::time::LOOP_PAUSE_TIME;
 use crate::{control::ControllerConfig, pub_sub::MessageParseError};
 use nats::{Message, Subscription};
 use serde::{Deserialize, Serialize};
@@ -14,9 +19,16 @@ impl PubSubClient for Supervisor {
     fn client_loop(mut self) -> Result<(), PubSubError> {
         let subject = Subject("command.>".into());
         let sub = self.subscribe(&subject)?;
+        let contr_status = self.subscribe(&Subject("controller.*.status".into()))?;
+        let heartbeats = self.subscribe(&HeartbeatMsg::wildcard_subject())?;
         let mut state = ClientState::Active;
         while state == ClientState::Active {
-            if let Some(msg) = sub.next() {
+            // Sensor commands are answered by the sensor itself.
+            if let Some(msg) = sub
+                .next_timeout(LOOP_PAUSE_TIME)
+                .ok()
+                .filter(|msg| !SensorCommand::is_sensor_command(&msg.subject))
+            {
                 state = match SupervisorSubMsg::try_from(&msg) {
                     Ok(cmd) => match self.process_command(cmd, &msg) {
                         Ok(state) => state,
@@ -30,6 +42,13 @@ impl PubSubClient for Supervisor {
                     }
                 };
             }
+            for msg in contr_status.try_iter() {
+                self.track_controller_status(&msg);
+            }
+            for msg in heartbeats.try_iter() {
+                self.track_heartbeat(&msg);
+            }
+            self.monitor_clients();
         }
         Ok(())
     }
@@ -51,8 +70,28 @@ pub enum SupervisorSubMsg {
     StopController { contr_id: ClientId },
     #[serde(rename = "switch_controller")]
     SwitchController { contr_data: NewContrData },
+    #[serde(rename = "add_sensor")]
+    AddSensor { sensor_config: SensorConfig },
+    #[serde(rename = "remove_sensor")]
+    RemoveSensor { sensor_id: ClientId },
+    #[serde(rename = "add_actor")]
+    AddActor { actor_config: ActorConfig },
+    #[serde(rename = "remove_actor")]
+    RemoveActor { actor_id: ClientId },
     #[serde(rename = "list_active_clients")]
     ListActiveClients,
+    #[serde(rename = "discover_sensors")]
+    DiscoverSensors,
+    #[serde(rename = "resume_session")]
+    ResumeSession,
+    #[serde(rename = "reload_config")]
+    ReloadConfig,
+    #[serde(rename = "start_program")]
+    StartProgram { program_config: ProgramConfig },
+    #[serde(rename = "stop_program")]
+    StopProgram { program_id: ClientId },
+    #[serde(rename = "register_remote")]
+    RegisterRemote { registration: RemoteRegistration },
     #[serde(rename = "stop")]
     Stop,
 }
@@ -73,7 +112,38 @@ impl TryFrom<&Message> for SupervisorSubMsg {
                 let contr_data: NewContrData = decode_nats_data(&msg.data)?;
                 Ok(SupervisorSubMsg::SwitchController { contr_data })
             }
+            "command.add_sensor" => {
+                let sensor_config: SensorConfig = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::AddSensor { sensor_config })
+            }
+            "command.remove_sensor" => {
+                let sensor_id: ClientId = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::RemoveSensor { sensor_id })
+            }
+            "command.add_actor" => {
+                let actor_config: ActorConfig = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::AddActor { actor_config })
+            }
+            "command.remove_actor" => {
+                let actor_id: ClientId = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::RemoveActor { actor_id })
+            }
             "command.list_active_clients" => Ok(SupervisorSubMsg::ListActiveClients),
+            "command.discover_sensors" => Ok(SupervisorSubMsg::DiscoverSensors),
+            "command.resume_session" => Ok(SupervisorSubMsg::ResumeSession),
+            "command.reload_config" => Ok(SupervisorSubMsg::ReloadConfig),
+            "command.start_program" => {
+                let program_config: ProgramConfig = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::StartProgram { program_config })
+            }
+            "command.stop_program" => {
+                let program_id: ClientId = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::StopProgram { program_id })
+            }
+            "command.register_remote" => {
+                let registration: RemoteRegistration = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::RegisterRemote { registration })
+            }
             "command.stop" => Ok(SupervisorSubMsg::Stop),
             _ => Err(MessageParseError::InvalidSubject(Subject(
                 msg.subject.clone(),
@@ -94,7 +164,25 @@ impl SupervisorSubMsg {
             SupervisorSubMsg::StopController { contr_id: _ } => {
                 Subject::from("command.stop_controller")
             }
+            SupervisorSubMsg::AddSensor { sensor_config: _ } => Subject::from("command.add_sensor"),
+            SupervisorSubMsg::RemoveSensor { sensor_id: _ } => {
+                Subject::from("command.remove_sensor")
+            }
+            SupervisorSubMsg::AddActor { actor_config: _ } => Subject::from("command.add_actor"),
+            SupervisorSubMsg::RemoveActor { actor_id: _ } => Subject::from("command.remove_actor"),
             SupervisorSubMsg::ListActiveClients => Subject::from("command.list_active_clients"),
+            SupervisorSubMsg::DiscoverSensors => Subject::from("command.discover_sensors"),
+            SupervisorSubMsg::ResumeSession => Subject::from("command.resume_session"),
+            SupervisorSubMsg::ReloadConfig => Subject::from("command.reload_config"),
+            SupervisorSubMsg::StartProgram { program_config: _ } => {
+                Subject::from("command.start_program")
+            }
+            SupervisorSubMsg::StopProgram { program_id: _ } => {
+                Subject::from("command.stop_program")
+            }
+            SupervisorSubMsg::RegisterRemote { registration: _ } => {
+                Subject::from("command.register_remote")
+            }
             SupervisorSubMsg::Stop => Subject::from("command.stop"),
         }
     }
@@ -106,16 +194,41 @@ impl From<SupervisorSubMsg> for PubSubMsg {
             SupervisorSubMsg::StartController { contr_data } => PubSubMsg(
                 serde_json::to_string(&contr_data).expect("SupervisorSubMsg serialization error"),
             ),
+            SupervisorSubMsg::StopController { contr_id } => PubSubMsg(
+                serde_json::to_string(&contr_id).expect("SupervisorSubMsg serialization error"),
+            ),
             SupervisorSubMsg::SwitchController { contr_data } => PubSubMsg(
                 serde_json::to_string(&contr_data).expect("SupervisorSubMsg serialization error"),
             ),
+            SupervisorSubMsg::AddSensor { sensor_config } => PubSubMsg(
+                serde_json::to_string(&sensor_config)
+                    .expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::RemoveSensor { sensor_id } => PubSubMsg(
+                serde_json::to_string(&sensor_id).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::AddActor { actor_config } => PubSubMsg(
+                serde_json::to_string(&actor_config).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::RemoveActor { actor_id } => PubSubMsg(
+                serde_json::to_string(&actor_id).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::StartProgram { program_config } => PubSubMsg(
+                serde_json::to_string(&program_config)
+                    .expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::StopProgram { program_id } => PubSubMsg(
+                serde_json::to_string(&program_id).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::RegisterRemote { registration } => PubSubMsg(
+                serde_json::to_string(&registration).expect("SupervisorSubMsg serialization error"),
+            ),
             // Empty message
             SupervisorSubMsg::ListActiveClients => PubSubMsg("".into()),
+            SupervisorSubMsg::DiscoverSensors => PubSubMsg("".into()),
+            SupervisorSubMsg::ResumeSession => PubSubMsg("".into()),
+            SupervisorSubMsg::ReloadConfig => PubSubMsg("".into()),
             SupervisorSubMsg::Stop => PubSubMsg("".into()),
-            _ => todo!(
-                "Supervisor command '{:?}' not implemented yet",
-                msg.subject()
-            ),
         }
     }
 }
@@ -134,8 +247,12 @@ impl NewContrData {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum SupervisorPubMsg {
+    /// Boxed, since the list is far larger than the other variants.
     #[serde(rename = "active_clients")]
-    ActiveClients(ActiveClientsList),
+    ActiveClients(Box<ActiveClientsList>),
+    /// DS18B20 sensors on the 1-wire bus, see [`ds18b20::discover`](crate::sensor::ds18b20::discover).
+    #[serde(rename = "discovered_sensors")]
+    DiscoveredSensors(Vec<DiscoveredDs18b20>),
     #[serde(rename = "kill_client")]
     KillClient { client_id: ClientId },
 }
@@ -146,6 +263,9 @@ impl SupervisorPubMsg {
             SupervisorPubMsg::ActiveClients(_) => {
                 Subject(String::from("supervisor.active_clients"))
             }
+            SupervisorPubMsg::DiscoveredSensors(_) => {
+                Subject(String::from("supervisor.discovered_sensors"))
+            }
             SupervisorPubMsg::KillClient { client_id } => {
                 Subject(format!("supervisor.kill.{}", client_id))
             }
@@ -159,6 +279,9 @@ impl From<SupervisorPubMsg> for PubSubMsg {
             SupervisorPubMsg::ActiveClients(clients) => PubSubMsg(
                 serde_json::to_string(&clients).expect("SupervisorPubMsg serialization error"),
             ),
+            SupervisorPubMsg::DiscoveredSensors(sensors) => PubSubMsg(
+                serde_json::to_string(&sensors).expect("SupervisorPubMsg serialization error"),
+            ),
             SupervisorPubMsg::KillClient { client_id } => PubSubMsg(
                 serde_json::to_string(&client_id).expect("SupervisorSubMsg serialization error"),
             ),
@@ -184,9 +307,21 @@ mod test {
                     offset_on: 10.0,
                     offset_off: 5.0,
                 },
+                failsafe: None,
+                aggregation: None,
+                output: None,
             },
             new_target: 16.0,
         };
         // TODO: assert_eq!(parsed, true_);
     }
+
+    #[test]
+    fn test_stop_controller_msg() {
+        let msg = SupervisorSubMsg::StopController {
+            contr_id: ClientId::from("mash"),
+        };
+        assert_eq!(msg.subject(), Subject::from("command.stop_controller"));
+        assert_eq!(PubSubMsg::from(msg).0, r#""mash""#);
+    }
 }
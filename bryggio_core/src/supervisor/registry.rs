@@ -0,0 +1,237 @@
+//! Registry of remote clients
+//!
+//! Sensors and actors can run on other nodes than the supervisor, e.g. on a sensor box.
+//! Such nodes register their clients with `command.register_remote`,
+//! and must re-register them before the registration expires.
+//! Registered remote clients count as active when the supervisor checks that all clients needed
+//! by a controller are available.
+use super::{Supervisor, SupervisorError};
+use crate::logger::{info, warning};
+use crate::pub_sub::{ClientId, PubSubError};
+use crate::time::TimeStamp;
+use nats::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Time to live for a registration, used by the nodes shipped with BryggIO.
+pub const REGISTRATION_TTL_MS: u64 = 10_000;
+
+/// Clients on a remote node, sent with `command.register_remote`.
+///
+/// A registration replaces any previous registration from the same node.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RemoteRegistration {
+    pub node_id: ClientId,
+    pub sensors: Vec<ClientId>,
+    pub actors: Vec<ClientId>,
+    pub ttl_ms: u64,
+}
+
+impl RemoteRegistration {
+    /// How often a node should re-register, leaves room for a couple of lost registrations.
+    pub fn reregistration_period(&self) -> Duration {
+        Duration::from_millis(self.ttl_ms / 3)
+    }
+}
+
+/// Reply to `command.register_remote`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RegistrationReport {
+    pub accepted: Vec<ClientId>,
+    pub rejected: Vec<(ClientId, String)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteKind {
+    #[serde(rename = "sensor")]
+    Sensor,
+    #[serde(rename = "actor")]
+    Actor,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RemoteClient {
+    pub node_id: ClientId,
+    pub kind: RemoteKind,
+    pub expires: TimeStamp,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RemoteClients(HashMap<ClientId, RemoteClient>);
+
+impl RemoteClients {
+    /// Register all clients of a node, except those claimed by another node.
+    pub(crate) fn register(
+        &mut self,
+        registration: &RemoteRegistration,
+        now: TimeStamp,
+    ) -> RegistrationReport {
+        self.0
+            .retain(|_, client| client.node_id != registration.node_id);
+        let expires = now + TimeStamp(u128::from(registration.ttl_ms));
+        let clients = registration
+            .sensors
+            .iter()
+            .map(|id| (id, RemoteKind::Sensor))
+            .chain(registration.actors.iter().map(|id| (id, RemoteKind::Actor)));
+
+        let mut report = RegistrationReport::default();
+        for (id, kind) in clients {
+            match self.0.get(id) {
+                Some(other) if other.expires > now => report.rejected.push((
+                    id.clone(),
+                    format!("Already registered by node '{}'", other.node_id),
+                )),
+                _ => {
+                    self.0.insert(
+                        id.clone(),
+                        RemoteClient {
+                            node_id: registration.node_id.clone(),
+                            kind,
+                            expires,
+                        },
+                    );
+                    report.accepted.push(id.clone());
+                }
+            }
+        }
+        report
+    }
+
+    pub(crate) fn contains_id(&self, id: &ClientId, now: TimeStamp) -> bool {
+        self.0.get(id).is_some_and(|client| client.expires > now)
+    }
+
+    /// Remove and return expired registrations.
+    pub(crate) fn prune(&mut self, now: TimeStamp) -> Vec<(ClientId, RemoteClient)> {
+        let expired: Vec<ClientId> = self
+            .0
+            .iter()
+            .filter(|(_, client)| client.expires <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| self.0.remove(&id).map(|client| (id, client)))
+            .collect()
+    }
+}
+
+impl Supervisor {
+    pub(crate) fn register_remote(
+        &mut self,
+        mut registration: RemoteRegistration,
+        msg: &Message,
+    ) -> Result<(), SupervisorError> {
+        let mut report = RegistrationReport::default();
+        // Local clients always take precedence.
+        for ids in [&mut registration.sensors, &mut registration.actors] {
+            ids.retain(|id| match self.client_is_active(id) {
+                true => {
+                    report
+                        .rejected
+                        .push((id.clone(), String::from("Id used by a local client")));
+                    false
+                }
+                false => true,
+            });
+        }
+        let node_report = self
+            .active_clients
+            .remote
+            .register(&registration, TimeStamp::now());
+        report.accepted.extend(node_report.accepted);
+        report.rejected.extend(node_report.rejected);
+        if !report.rejected.is_empty() {
+            warning(
+                self,
+                format!(
+                    "Rejected remote clients from node '{}': {:?}",
+                    registration.node_id, report.rejected
+                ),
+                "supervisor",
+            );
+        }
+
+        // Nodes re-register periodically and need not ask for a reply.
+        if msg.reply.is_some() {
+            msg.respond(serde_json::to_string(&report).expect("Can always serialize"))
+                .map_err(|err| PubSubError::Reply {
+                    task: "register remote",
+                    msg: msg.clone(),
+                    source: err,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Drop expired registrations, warns if a controller depends on the expired client.
+    pub(crate) fn prune_remote_clients(&mut self) {
+        for (id, client) in self.active_clients.remote.prune(TimeStamp::now()) {
+            match self.ensure_unused(&id) {
+                Ok(()) => info(
+                    self,
+                    format!(
+                        "Registration of remote client '{}' on node '{}' expired",
+                        id, client.node_id
+                    ),
+                    "supervisor",
+                ),
+                Err(err) => warning(
+                    self,
+                    format!(
+                        "Registration of remote client '{}' on node '{}' expired, {}",
+                        id, client.node_id, err
+                    ),
+                    "supervisor",
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn registration(node: &str, sensors: &[&str]) -> RemoteRegistration {
+        RemoteRegistration {
+            node_id: ClientId::from(node),
+            sensors: sensors.iter().map(|id| ClientId::from(*id)).collect(),
+            actors: vec![],
+            ttl_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn test_register_and_expire() {
+        let mut remote = RemoteClients::default();
+        let report = remote.register(&registration("box", &["mash"]), TimeStamp(0));
+        assert_eq!(report.accepted, vec![ClientId::from("mash")]);
+        assert!(remote.contains_id(&ClientId::from("mash"), TimeStamp(999)));
+        assert!(!remote.contains_id(&ClientId::from("mash"), TimeStamp(1000)));
+        assert_eq!(remote.prune(TimeStamp(1000)).len(), 1);
+        assert!(remote.0.is_empty());
+    }
+
+    #[test]
+    fn test_reregister_replaces() {
+        let mut remote = RemoteClients::default();
+        remote.register(&registration("box", &["mash", "boil"]), TimeStamp(0));
+        remote.register(&registration("box", &["mash"]), TimeStamp(500));
+        assert!(remote.contains_id(&ClientId::from("mash"), TimeStamp(1200)));
+        assert!(!remote.contains_id(&ClientId::from("boil"), TimeStamp(600)));
+    }
+
+    #[test]
+    fn test_conflicting_nodes() {
+        let mut remote = RemoteClients::default();
+        remote.register(&registration("box", &["mash"]), TimeStamp(0));
+        let report = remote.register(&registration("other_box", &["mash"]), TimeStamp(500));
+        assert!(report.accepted.is_empty());
+        assert_eq!(report.rejected.len(), 1);
+        let report = remote.register(&registration("other_box", &["mash"]), TimeStamp(1500));
+        assert_eq!(report.accepted, vec![ClientId::from("mash")]);
+    }
+}
@@ -0,0 +1,261 @@
+//! Hot reload of the supervisor config file
+//!
+//! On `command.reload_config` the config file is parsed and validated again,
+//! and its `hardware` section is compared with the one currently in use,
+//! which includes the sensors and actors added or removed by command since the last reload.
+//! Only the sensors and actors that were added, removed or reconfigured are stopped or started,
+//! all other clients keep running undisturbed.
+//! The restart and session settings are replaced as a whole,
+//! while changes to the `general` and `nats` sections still require a full restart.
+use super::config::{SupervisorConfig, SupervisorConfigError};
+use super::{Supervisor, SupervisorError};
+use crate::actor::ActorConfig;
+use crate::logger::info;
+use crate::pub_sub::{nats_client::NatsClientConfig, ClientId, PubSubError};
+use crate::sensor::SensorConfig;
+use nats::Message;
+use serde::{Deserialize, Serialize};
+
+/// Changes made by a config reload, sent as reply to `command.reload_config`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ReloadReport {
+    pub added: Vec<ClientId>,
+    pub removed: Vec<ClientId>,
+    pub reconfigured: Vec<ClientId>,
+    pub failed: Vec<(ClientId, String)>,
+}
+
+/// Difference between two lists of client configs, matched on client id.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ConfigDiff<C> {
+    pub(crate) added: Vec<C>,
+    pub(crate) removed: Vec<C>,
+    /// Old and new config of clients present in both lists.
+    pub(crate) changed: Vec<(C, C)>,
+}
+
+/// Sensor or actor config which can be started and stopped by the supervisor.
+pub(crate) trait HardwareConfig: Clone + PartialEq {
+    fn id(&self) -> &ClientId;
+    fn start(&self, supervisor: &mut Supervisor) -> Result<(), SupervisorError>;
+    fn stop(&self, supervisor: &mut Supervisor) -> Result<(), SupervisorError>;
+}
+
+impl HardwareConfig for SensorConfig {
+    fn id(&self) -> &ClientId {
+        &self.id
+    }
+
+    fn start(&self, supervisor: &mut Supervisor) -> Result<(), SupervisorError> {
+        let nats_config = NatsClientConfig::from(supervisor.config.nats.server.clone());
+        supervisor.add_sensor(self.clone(), &nats_config)
+    }
+
+    fn stop(&self, supervisor: &mut Supervisor) -> Result<(), SupervisorError> {
+        supervisor.remove_sensor(&self.id)
+    }
+}
+
+impl HardwareConfig for ActorConfig {
+    fn id(&self) -> &ClientId {
+        &self.id
+    }
+
+    fn start(&self, supervisor: &mut Supervisor) -> Result<(), SupervisorError> {
+        let nats_config = NatsClientConfig::from(supervisor.config.nats.server.clone());
+        supervisor.add_actor(self.clone(), &nats_config)
+    }
+
+    fn stop(&self, supervisor: &mut Supervisor) -> Result<(), SupervisorError> {
+        supervisor.remove_actor(&self.id)
+    }
+}
+
+pub(crate) fn diff<C: HardwareConfig>(old: &[C], new: &[C]) -> ConfigDiff<C> {
+    let find = |list: &[C], id: &ClientId| list.iter().find(|c| c.id() == id).cloned();
+    ConfigDiff {
+        added: new
+            .iter()
+            .filter(|c| find(old, c.id()).is_none())
+            .cloned()
+            .collect(),
+        removed: old
+            .iter()
+            .filter(|c| find(new, c.id()).is_none())
+            .cloned()
+            .collect(),
+        changed: old
+            .iter()
+            .filter_map(|c| find(new, c.id()).map(|new_c| (c.clone(), new_c)))
+            .filter(|(old_c, new_c)| old_c != new_c)
+            .collect(),
+    }
+}
+
+impl Supervisor {
+    /// Re-read the config file and apply the hardware changes.
+    ///
+    /// A change that cannot be applied, e.g. removing a sensor used by a controller,
+    /// is reported and the old config of that client is kept,
+    /// so that the change is attempted again on the next reload.
+    pub(crate) fn reload_config(&mut self) -> Result<ReloadReport, SupervisorError> {
+        let path = self.config.config_file.clone().ok_or_else(|| {
+            SupervisorError::Config(SupervisorConfigError::Config(String::from(
+                "Supervisor was not started from a config file",
+            )))
+        })?;
+        let new_config = SupervisorConfig::try_new(&path)?;
+        info(
+            self,
+            format!("Reloading config from '{}'", path.display()),
+            "supervisor",
+        );
+
+        let mut report = ReloadReport::default();
+        // Only sensors started from now on use a new 1-wire root.
+        self.config.hardware.one_wire_dir = new_config.hardware.one_wire_dir.clone();
+        let old_sensors = self.config.hardware.sensors.clone();
+        let old_actors = self.config.hardware.actors.clone();
+        self.config.hardware.sensors =
+            self.apply_diff(old_sensors, &new_config.hardware.sensors, &mut report);
+        self.config.hardware.actors =
+            self.apply_diff(old_actors, &new_config.hardware.actors, &mut report);
+        self.config.restart = new_config.restart;
+        self.config.session = new_config.session;
+        info(self, format!("Config reloaded: {:?}", report), "supervisor");
+        Ok(report)
+    }
+
+    pub(crate) fn reply_reload_config(&mut self, msg: &Message) -> Result<(), SupervisorError> {
+        let response = match self.reload_config() {
+            Ok(report) => serde_json::to_string(&report).expect("Can always serialize"),
+            Err(err) => format!("Failed to reload config: {}", err),
+        };
+        msg.respond(response).map_err(|err| PubSubError::Reply {
+            task: "reload config",
+            msg: msg.clone(),
+            source: err,
+        })?;
+        Ok(())
+    }
+
+    /// Apply the changes between `old` and `new`, returns the configs now in use.
+    fn apply_diff<C: HardwareConfig>(
+        &mut self,
+        old: Vec<C>,
+        new: &[C],
+        report: &mut ReloadReport,
+    ) -> Vec<C> {
+        let diff = diff(&old, new);
+        let mut in_use = old;
+
+        for config in diff.removed {
+            match self.stop_hardware(&config) {
+                Ok(()) => {
+                    in_use.retain(|c| c.id() != config.id());
+                    report.removed.push(config.id().clone());
+                }
+                Err(err) => report.failed.push((config.id().clone(), err.to_string())),
+            }
+        }
+
+        for (old_config, new_config) in diff.changed {
+            let id = new_config.id().clone();
+            if let Err(err) = self.stop_hardware(&old_config) {
+                report.failed.push((id, err.to_string()));
+                continue;
+            }
+            in_use.retain(|c| c.id() != &id);
+            match new_config.start(self) {
+                Ok(()) => {
+                    in_use.push(new_config);
+                    report.reconfigured.push(id);
+                }
+                Err(err) => report.failed.push((id, err.to_string())),
+            }
+        }
+
+        for config in diff.added {
+            match config.start(self) {
+                Ok(()) => {
+                    report.added.push(config.id().clone());
+                    in_use.push(config);
+                }
+                Err(err) => report.failed.push((config.id().clone(), err.to_string())),
+            }
+        }
+        in_use
+    }
+
+    /// Stop a client, a client which has already exited counts as stopped.
+    fn stop_hardware<C: HardwareConfig>(&mut self, config: &C) -> Result<(), SupervisorError> {
+        match config.stop(self) {
+            Err(SupervisorError::Missing(_)) => Ok(()),
+            res => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sensor::SensorType;
+    use crate::supervisor::config::Hardware;
+
+    fn sensor(id: &str, delay: u64) -> SensorConfig {
+        SensorConfig {
+            id: ClientId::from(id),
+            type_: SensorType::Dummy(delay),
+            sampling: None,
+            calibration: None,
+            filters: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![
+            sensor("mash", 1000),
+            sensor("boil", 1000),
+            sensor("hlt", 500),
+        ];
+        let new = vec![
+            sensor("mash", 1000),
+            sensor("boil", 2000),
+            sensor("ferm", 500),
+        ];
+        let diff = diff(&old, &new);
+        assert_eq!(diff.added, vec![sensor("ferm", 500)]);
+        assert_eq!(diff.removed, vec![sensor("hlt", 500)]);
+        assert_eq!(
+            diff.changed,
+            vec![(sensor("boil", 1000), sensor("boil", 2000))]
+        );
+    }
+
+    #[test]
+    fn test_diff_renamed() {
+        let diff = diff(&[sensor("mash", 1000)], &[sensor("mash_tun", 1000)]);
+        assert_eq!(diff.added, vec![sensor("mash_tun", 1000)]);
+        assert_eq!(diff.removed, vec![sensor("mash", 1000)]);
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn test_diff_after_commands() {
+        let mut hardware = Hardware::dummy();
+        hardware.sensors = vec![sensor("mash", 1000), sensor("hlt", 1000)];
+        // Added by command and then written to the file, removed by command but still in it.
+        hardware.track_sensor(sensor("ferm", 500));
+        hardware.forget(&ClientId::from("hlt"));
+        let file = vec![
+            sensor("mash", 1000),
+            sensor("hlt", 1000),
+            sensor("ferm", 500),
+        ];
+        let diff = diff(&hardware.sensors, &file);
+        assert_eq!(diff.added, vec![sensor("hlt", 1000)]);
+        assert!(diff.removed.is_empty());
+        assert!(diff.changed.is_empty());
+    }
+}
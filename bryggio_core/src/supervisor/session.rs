@@ -0,0 +1,173 @@
+//! Persistence of the running brew session
+//!
+//! The supervisor writes its active clients, including the controllers and their targets,
+//! to a state file whenever a controller is started, stopped, exits or gets a new target.
+//! A controller awaiting a restart is kept in the file.
+//! If the supervisor is restarted, e.g. after a power cut, the controllers can be resumed
+//! from the file, either on start-up or with the `command.resume_session` command.
+use super::{ActiveClientsList, Supervisor, SupervisorError};
+use crate::control::pub_sub::ControllerPubMsg;
+use crate::logger::{error, info};
+use crate::pub_sub::{nats_client::decode_nats_data, ClientId, PubSubError};
+use nats::Message;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+/// Outcome of resuming a session, sent as reply to `command.resume_session`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ResumeReport {
+    pub resumed: Vec<ClientId>,
+    pub failed: Vec<(ClientId, String)>,
+}
+
+impl Supervisor {
+    /// Start the controllers stored in the state file.
+    ///
+    /// Controllers that fail to start, e.g. because a sensor is missing, are reported but do not
+    /// stop the others from being resumed.
+    pub(crate) fn resume_session(&mut self) -> Result<ResumeReport, SupervisorError> {
+        let path = self
+            .config
+            .session
+            .state_file
+            .clone()
+            .ok_or(SessionError::Disabled)?;
+        let session = load(&path)?;
+        let mut report = ResumeReport::default();
+        for (id, config) in session.controllers {
+            let target = session.controller_targets.get(&id).copied().unwrap_or(0.0);
+            match self.common_start_controller(config, target, None) {
+                Ok(()) => {
+                    info(
+                        self,
+                        format!("Resumed controller '{}' with target {}", id, target),
+                        "supervisor",
+                    );
+                    report.resumed.push(id);
+                }
+                Err(err) => {
+                    error(
+                        self,
+                        format!("Failed resuming controller '{}': {}", id, err),
+                        "supervisor",
+                    );
+                    report.failed.push((id, err.to_string()));
+                }
+            }
+        }
+        self.save_session();
+        Ok(report)
+    }
+
+    pub(crate) fn reply_resume_session(&mut self, msg: &Message) -> Result<(), SupervisorError> {
+        let response = match self.resume_session() {
+            Ok(report) => serde_json::to_string(&report).expect("Can always serialize"),
+            Err(err) => format!("Failed to resume session: {}", err),
+        };
+        msg.respond(response).map_err(|err| PubSubError::Reply {
+            task: "resume session",
+            msg: msg.clone(),
+            source: err,
+        })?;
+        Ok(())
+    }
+
+    /// Write the active clients to the state file, if one is configured.
+    ///
+    /// Failing to save is logged, it should never stop a running brew.
+    pub(crate) fn save_session(&self) {
+        if let Some(path) = &self.config.session.state_file {
+            if let Err(err) = save(path, &ActiveClientsList::from(&self.active_clients)) {
+                error(
+                    self,
+                    format!("Failed saving session to '{}': {}", path.display(), err),
+                    "supervisor",
+                );
+            }
+        }
+    }
+
+    /// Keep track of controller targets and parameters set directly with
+    /// `controller.<id>.set_target` and `controller.<id>.set_params`.
+    pub(crate) fn track_controller_status(&mut self, msg: &Message) {
+        if let Ok(ControllerPubMsg::Status {
+            id, target, type_, ..
+        }) = decode_nats_data::<ControllerPubMsg>(&msg.data)
+        {
+            let params_changed = match self.active_clients.controllers.get_mut(&id) {
+                // A late status from a controller replaced by another type is ignored.
+                Some((_, config)) if config.type_.same_type(&type_) => {
+                    let changed = config.type_ != type_;
+                    config.type_ = type_;
+                    changed
+                }
+                Some(_) => false,
+                None => return,
+            };
+            let previous = self.active_clients.controller_targets.insert(id, target);
+            if previous != Some(target) || params_changed {
+                self.save_session();
+            }
+        }
+    }
+}
+
+pub(crate) fn save(path: &Path, clients: &ActiveClientsList) -> Result<(), SessionError> {
+    let session = serde_json::to_string_pretty(clients)
+        .map_err(|err| SessionError::Parse(err.to_string()))?;
+    // Write to a temporary file first, so that a crash mid-write never leaves a broken state file.
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, session)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub(crate) fn load(path: &Path) -> Result<ActiveClientsList, SessionError> {
+    let session = fs::read_to_string(path)?;
+    serde_json::from_str(&session).map_err(|err| SessionError::Parse(err.to_string()))
+}
+
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("No session state file configured")]
+    Disabled,
+    #[error("Error accessing session state file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Error parsing session state: {0}")]
+    Parse(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::ControllerConfig;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_session_roundtrip() {
+        let contr = ControllerConfig::dummy();
+        let id = contr.controller_id.clone();
+        let clients = ActiveClientsList {
+            sensors: HashMap::new(),
+            actors: HashMap::new(),
+            controllers: HashMap::from([(id.clone(), contr)]),
+            controller_targets: HashMap::from([(id.clone(), 64.0)]),
+            programs: HashMap::new(),
+            misc: vec![ClientId::from("log")],
+            health: HashMap::new(),
+            remote: Default::default(),
+        };
+        let path = std::env::temp_dir().join(format!(
+            "bryggio_session_roundtrip_{}.json",
+            std::process::id()
+        ));
+        save(&path, &clients).unwrap();
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(loaded.contains_id(&id));
+        assert_eq!(loaded.controller_targets.get(&id), Some(&64.0));
+    }
+}
@@ -0,0 +1,206 @@
+//! Orderly shutdown of the supervisor
+//!
+//! On `command.stop` every client is stopped before the supervisor exits:
+//! first the programs and controllers, so that no new targets or actor signals are sent,
+//! then the actors, which turn themselves off before exiting,
+//! then the sensors and last the loggers.
+//!
+//! The session file is left as is, so that the controllers can be resumed on the next start.
+use super::monitor::join;
+use super::{Supervisor, SupervisorError};
+use crate::actor::pub_sub::{actor_turn_off_subject, TURN_OFF_REPLY};
+use crate::actor::ActorError;
+use crate::logger::{error, info};
+use crate::pub_sub::{nats_client::decode_nats_data, ClientId, PubSubError, PubSubMsg};
+use crate::supervisor::pub_sub::SupervisorPubMsg;
+use crate::time::LOOP_PAUSE_TIME;
+use nats::Message;
+use serde::{Deserialize, Serialize};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Time to wait for a client to reply to the kill request, and then for its thread to finish.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of a shutdown, sent as reply to `command.stop`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ShutdownReport {
+    pub stopped: Vec<ClientId>,
+    pub failed: Vec<(ClientId, String)>,
+}
+
+impl Supervisor {
+    /// Stop all clients, returns when all of them have exited or timed out.
+    pub(crate) fn shutdown(&mut self) -> ShutdownReport {
+        info(self, String::from("Shutting down"), "supervisor");
+        self.pending_restarts.clear();
+        let mut report = ShutdownReport::default();
+
+        let programs: Vec<ClientId> = self.active_clients.programs.keys().cloned().collect();
+        let controllers: Vec<ClientId> = self.active_clients.controllers.keys().cloned().collect();
+        for id in programs.into_iter().chain(controllers) {
+            let res = self.stop_client(&id);
+            self.record_stop(&mut report, id, res);
+        }
+
+        let actors: Vec<ClientId> = self.active_clients.actors.keys().cloned().collect();
+        for id in actors {
+            let res = self.stop_actor(&id);
+            self.record_stop(&mut report, id, res);
+        }
+
+        let sensors: Vec<ClientId> = self.active_clients.sensors.keys().cloned().collect();
+        for id in sensors {
+            let res = self.stop_client(&id);
+            self.record_stop(&mut report, id, res);
+        }
+
+        // The log client goes last, so that it can log the shutdown of the others.
+        let mut misc: Vec<ClientId> = self.active_clients.misc.keys().cloned().collect();
+        misc.sort_by_key(|id| id.as_ref() == "log");
+        for id in misc {
+            let res = self.stop_client(&id);
+            self.record_stop(&mut report, id, res);
+        }
+        info(
+            self,
+            format!("Supervisor shut down: {:?}", report),
+            "supervisor",
+        );
+        report
+    }
+
+    pub(crate) fn reply_shutdown(&mut self, msg: &Message) -> Result<(), SupervisorError> {
+        let report = self.shutdown();
+        // `command.stop` may be published without expecting a reply.
+        if msg.reply.is_some() {
+            msg.respond(serde_json::to_string(&report).expect("Can always serialize"))
+                .map_err(|err| PubSubError::Reply {
+                    task: "shutdown",
+                    msg: msg.clone(),
+                    source: err,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Stop an actor, making sure it is off if the actor client fails to stop.
+    ///
+    /// While the client thread runs it owns the output, so it is asked to turn the output off.
+    /// The output is only opened again by the supervisor once that thread has exited.
+    fn stop_actor(&mut self, id: &ClientId) -> Result<(), SupervisorError> {
+        let config = self
+            .active_clients
+            .actors
+            .get(id)
+            .map(|(_, config)| config.clone());
+        let err = match self.stop_client(id) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        let running = self
+            .active_clients
+            .actors
+            .get(id)
+            .is_some_and(|(handle, _)| !handle.is_finished());
+        let fallback = match running {
+            true => self.turn_off_running(id),
+            false => {
+                if let Some((handle, _)) = self.active_clients.actors.remove(id) {
+                    // The thread has exited and released the output, only the output matters now.
+                    let _ = join(id, handle);
+                }
+                match config.map(|config| config.get_actor()) {
+                    Some(Ok(mut actor)) => actor.turn_off().map_err(|err| err.to_string()),
+                    Some(Err(err)) => Err(err.to_string()),
+                    None => Err(String::from("config missing")),
+                }
+            }
+        };
+        let action = match running {
+            true => "through its client",
+            false => "directly",
+        };
+        let msg = match fallback {
+            Ok(()) => format!("{}, actor turned off {}", err, action),
+            Err(fallback_err) => format!(
+                "{}, turning off actor {} failed: {}",
+                err, action, fallback_err
+            ),
+        };
+        Err(SupervisorError::Actor(ActorError::Generic(msg)))
+    }
+
+    /// Ask a running actor client to turn its output off.
+    fn turn_off_running(&self, id: &ClientId) -> Result<(), String> {
+        let reply = self
+            .client
+            .request_timeout(
+                &actor_turn_off_subject(id),
+                &PubSubMsg::empty(),
+                SHUTDOWN_TIMEOUT,
+            )
+            .map_err(|err| err.to_string())?;
+        match String::from_utf8_lossy(&reply.data) {
+            reply if reply == TURN_OFF_REPLY => Ok(()),
+            reply => Err(reply.into_owned()),
+        }
+    }
+
+    fn stop_client(&mut self, id: &ClientId) -> Result<(), SupervisorError> {
+        let msg = SupervisorPubMsg::KillClient {
+            client_id: id.clone(),
+        };
+        let reply = self
+            .client
+            .request_timeout(&msg.subject(), &msg.into(), SHUTDOWN_TIMEOUT)?;
+        if self.active_clients.actors.contains_key(id) {
+            decode_nats_data::<Result<(), String>>(&reply.data)
+                .map_err(PubSubError::from)?
+                .map_err(|err| SupervisorError::Actor(ActorError::Generic(err)))?;
+        }
+        self.wait_for_exit(id, SHUTDOWN_TIMEOUT)?;
+        let handle = self
+            .active_clients
+            .remove(id)
+            .or_else(|| self.active_clients.misc.remove(id))
+            .ok_or_else(|| SupervisorError::Missing(id.clone()))?;
+        join(id, handle)
+    }
+
+    /// Wait for a client thread to finish, giving up if it has not finished within `timeout`.
+    ///
+    /// The client is kept until its thread has finished.
+    fn wait_for_exit(&self, id: &ClientId, timeout: Duration) -> Result<(), SupervisorError> {
+        let start = Instant::now();
+        loop {
+            match self.active_clients.handle(id) {
+                None => return Err(SupervisorError::Missing(id.clone())),
+                Some(handle) if handle.is_finished() => return Ok(()),
+                Some(_) if start.elapsed() > timeout => {
+                    return Err(SupervisorError::Timeout(id.clone()))
+                }
+                Some(_) => sleep(LOOP_PAUSE_TIME),
+            }
+        }
+    }
+
+    fn record_stop(
+        &self,
+        report: &mut ShutdownReport,
+        id: ClientId,
+        res: Result<(), SupervisorError>,
+    ) {
+        match res {
+            Ok(()) => report.stopped.push(id),
+            Err(err) => {
+                error(
+                    self,
+                    format!("Failed stopping client '{}': {}", id, err),
+                    "supervisor",
+                );
+                report.failed.push((id, err.to_string()));
+            }
+        }
+    }
+}
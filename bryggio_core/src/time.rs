@@ -17,7 +17,8 @@ impl TimeStamp {
     }
 }
 
-pub(crate) const LOOP_PAUSE_TIME: Duration = Duration::from_millis(100);
+/// Pause between two iterations of a client loop.
+pub const LOOP_PAUSE_TIME: Duration = Duration::from_millis(100);
 
 #[cfg(test)]
 mod test {
@@ -6,6 +6,10 @@ use bryggio_core::pub_sub::{
     ClientId, ClientState, PubSubClient, PubSubError,
 };
 use bryggio_core::sensor::{SensorClient, SensorConfig, SensorError};
+use bryggio_core::supervisor::{
+    pub_sub::SupervisorSubMsg,
+    registry::{RemoteRegistration, REGISTRATION_TTL_MS},
+};
 use bryggio_core::{
     logger::{error, LogLevel},
     supervisor::config::Hardware,
@@ -15,14 +19,16 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::thread;
 use thiserror::Error;
 mod pub_sub;
 
 pub struct SensorBox {
+    id: ClientId,
     client: NatsClient,
     active_clients: ActiveClients,
+    one_wire_dir: PathBuf,
 }
 
 impl SensorBox {
@@ -31,8 +37,10 @@ impl SensorBox {
         println!("{:?}", nats_config);
         let client = NatsClient::try_new(&nats_config)?;
         let mut sensor_box = SensorBox {
+            id: ClientId(config.general.client_name.clone()),
             client,
             active_clients: ActiveClients::new(),
+            one_wire_dir: config.hardware.one_wire_dir.clone(),
         };
 
         for sensor_config in config.hardware.sensors {
@@ -64,11 +72,8 @@ impl SensorBox {
         match self.active_clients.sensors.get(id) {
             Some(_) => Err(SensorBoxError::AlreadyActive(id.clone())),
             None => {
-                let sensor = SensorClient::new(
-                    sensor_config.id.clone(),
-                    sensor_config.create_sensor()?,
-                    config,
-                );
+                let sensor = sensor_config.create_sensor(&self.one_wire_dir)?;
+                let sensor = SensorClient::new(&sensor_config, sensor, config);
                 let handle = thread::spawn(|| sensor.client_loop().map_err(|err| err.into()));
                 self.active_clients
                     .sensors
@@ -97,6 +102,21 @@ impl SensorBox {
         }
     }
 
+    /// Register the sensors and actors of the box with the supervisor.
+    fn registration(&self) -> RemoteRegistration {
+        RemoteRegistration {
+            node_id: self.id.clone(),
+            sensors: self.active_clients.sensors.keys().cloned().collect(),
+            actors: self.active_clients.actors.keys().cloned().collect(),
+            ttl_ms: REGISTRATION_TTL_MS,
+        }
+    }
+
+    fn register(&self, registration: RemoteRegistration) -> Result<(), SensorBoxError> {
+        let msg = SupervisorSubMsg::RegisterRemote { registration };
+        Ok(self.publish(&msg.subject(), &msg.into())?)
+    }
+
     fn handle_err(&self, err: SensorBoxError) -> ClientState {
         error(self, err.to_string(), "sensor_box");
         ClientState::Active
@@ -1,17 +1,29 @@
 use crate::{ActiveClientsList, SensorBox};
 use bryggio_core::pub_sub::{ClientId, ClientState, PubSubClient, PubSubError, Subject};
 use bryggio_core::pub_sub::{MessageParseError, PubSubMsg};
+use bryggio_core::time::LOOP_PAUSE_TIME;
 use nats::{Message, Subscription};
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::time::Instant;
 
 impl PubSubClient for SensorBox {
     fn client_loop(mut self) -> Result<(), PubSubError> {
         let subject = Subject("sensor_box.>".into());
         let sub = self.subscribe(&subject)?;
         let mut state = ClientState::Active;
+        let mut last_registration: Option<Instant> = None;
         while state == ClientState::Active {
-            if let Some(msg) = sub.next() {
+            let registration = self.registration();
+            if last_registration
+                .is_none_or(|last| last.elapsed() >= registration.reregistration_period())
+            {
+                if let Err(err) = self.register(registration) {
+                    self.handle_err(err);
+                }
+                last_registration = Some(Instant::now());
+            }
+            if let Ok(msg) = sub.next_timeout(LOOP_PAUSE_TIME) {
                 state = match SensorBoxSubMsg::try_from(&msg) {
                     Ok(cmd) => match self.process_command(cmd, &msg) {
                         Ok(state) => state,
@@ -1,11 +1,19 @@
 #![forbid(unsafe_code)]
-use bryggio_core::pub_sub::{nats_client::run_nats_server, PubSubClient, PubSubError};
-use bryggio_core::supervisor::{config::SupervisorConfig, Supervisor, SupervisorError};
+use bryggio_core::pub_sub::{
+    nats_client::{run_nats_server, NatsClient, NatsClientConfig},
+    ClientId, PubSubClient, PubSubError,
+};
+use bryggio_core::supervisor::{
+    config::SupervisorConfig, pub_sub::SupervisorSubMsg, Supervisor, SupervisorError,
+};
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
+use tokio::signal::unix::{signal, SignalKind};
 
 // Note: I have started some trials into converting the code base to be async.
-// At present, none of it is async, except for the functions in this file, which have no practical effect.
+// At present, none of it is async, except for the functions in this file,
+// which are only used to wait for the supervisor and for shutdown signals.
 #[tokio::main]
 async fn main() {
     if let Err(err) = run_supervisor().await {
@@ -19,6 +27,9 @@ async fn main() {
 /// bryggio process.
 /// The NATS server is started in a separate process then,
 /// a Supervisor client is started which runs indefinetly.
+///
+/// SIGINT and SIGTERM are handled like the `command.stop` command,
+/// i.e. all clients are stopped and every actor turned off before the process exits.
 async fn run_supervisor() -> Result<(), SupervisorError> {
     let opt = Opt::from_args();
     match opt {
@@ -28,17 +39,60 @@ async fn run_supervisor() -> Result<(), SupervisorError> {
             let mut nats_server_child =
                 run_nats_server(&config.nats.server, &config.nats.bin_path)?;
 
-            let supervisor = Supervisor::init_from_config(config)?;
-            supervisor.client_loop()?;
+            let res = supervise(config).await;
 
-            // If the process exits, kill the NATS subprocess.
-            nats_server_child
+            // The NATS server runs in its own process group and does not get the SIGINT,
+            // so it is killed on every exit, also if the supervisor failed to start.
+            let killed = nats_server_child
                 .kill()
-                .map_err(|err| PubSubError::Server(err.to_string()).into())
+                .map_err(|err| PubSubError::Server(err.to_string()).into());
+            res.and(killed)
+        }
+    }
+}
+
+/// Run the supervisor until it exits or a shutdown signal is received.
+async fn supervise(config: SupervisorConfig) -> Result<(), SupervisorError> {
+    let nats_config = NatsClientConfig::from(config.nats.server.clone());
+    let supervisor = Supervisor::init_from_config(config)?;
+    let mut sup_handle = tokio::task::spawn_blocking(|| supervisor.client_loop());
+
+    let res = tokio::select! {
+        res = &mut sup_handle => res,
+        sig = shutdown_signal() => {
+            sig.map_err(PubSubError::from)?;
+            request_stop(&nats_config)?;
+            sup_handle.await
         }
+    };
+    res.map_err(|_| SupervisorError::ThreadJoin(ClientId::from("supervisor")))??;
+    Ok(())
+}
+
+/// How long to wait for the supervisor to stop all clients and reply with its shutdown report.
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Wait for SIGINT or SIGTERM.
+async fn shutdown_signal() -> std::io::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res,
+        _ = sigterm.recv() => Ok(()),
     }
 }
 
+/// Ask the supervisor to shut down, like any other client would.
+///
+/// Gives up after [`STOP_TIMEOUT`], so that a hanging supervisor does not keep the process alive.
+fn request_stop(nats_config: &NatsClientConfig) -> Result<(), PubSubError> {
+    println!("Shutting down supervisor");
+    let client = NatsClient::try_new(nats_config)?;
+    let msg = SupervisorSubMsg::Stop;
+    let report = client.request_timeout(&msg.subject(), &msg.into(), STOP_TIMEOUT)?;
+    println!("{}", String::from_utf8_lossy(&report.data));
+    Ok(())
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "bryggio-supervisor", about = "Supervisor client for BryggIO")]
 pub enum Opt {